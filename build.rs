@@ -33,9 +33,9 @@ br###"// GENERATED BY build.rs
     }
 
     f.write(br###"
-// Execute the solution for a particular day (1..25).
-pub fn solve(day: usize) -> Result<(), Error> {
-    let mut solver: Box<AdventSolver> = match day {
+// Create the solver for a particular day (1..25).
+fn solver(day: usize) -> Result<Box<dyn AdventSolver>, Error> {
+    let solver: Box<dyn AdventSolver> = match day {
 "###).unwrap();
     for module in &days {
         let day_num = module[3..].parse::<usize>().unwrap();
@@ -45,7 +45,14 @@ pub fn solve(day: usize) -> Result<(), Error> {
     f.write(
 br###"         _ => { return Err(format_err!("Invalid day number: {}", day)); }
     };
-    solver.solve()
+    Ok(solver)
+}
+
+// Execute the solution for a particular day (1..25).
+pub fn solve(day: usize, ctx: &mut Context) -> Result<(), Error> {
+    let mut solver = solver(day)?;
+    ctx.check_params(solver.params())?;
+    solver.solve(ctx)
 }
 "###).unwrap();
 }
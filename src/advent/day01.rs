@@ -1,43 +1,372 @@
-use advent::AdventSolver;
+use advent::{AdventSolver, Context};
 use failure::Error;
-use std::collections::HashSet;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+use util::evidence::Evidence;
 
 #[derive(Default)]
 pub struct Solver;
 
+// Running statistics over the changes as they come in. Memory use is
+// constant, apart from the frequencies remembered while looking for the
+// first repeat, and those are dropped as soon as it's found.
+pub struct Stats {
+    pub current: isize,
+    pub min: isize,
+    pub max: isize,
+    pub changes: usize,
+    // The first frequency seen twice, and how many changes it took.
+    pub first_repeat: Option<(isize, usize)>,
+    seen: Option<HashSet<isize>>,
+    // Report overflow instead of wrapping.
+    checked: bool,
+}
+
+impl Stats {
+    pub fn new(initial_frequency: isize, checked: bool, track_repeat: bool)
+            -> Stats {
+        Stats {
+            current: initial_frequency,
+            min: initial_frequency,
+            max: initial_frequency,
+            changes: 0,
+            first_repeat: None,
+            seen: if track_repeat {
+                Some(vec![initial_frequency].into_iter().collect())
+            } else {
+                None
+            },
+            checked: checked,
+        }
+    }
+
+    pub fn add(&mut self, change: isize) -> Result<(), Error> {
+        self.current = if self.checked {
+            self.current.checked_add(change).ok_or_else(|| {
+                format_err!("Frequency overflowed: {} + {}",
+                            self.current, change)
+            })?
+        } else {
+            self.current.wrapping_add(change)
+        };
+        self.changes += 1;
+        self.min = self.min.min(self.current);
+        self.max = self.max.max(self.current);
+        let repeated = match self.seen {
+            Some(ref mut seen) => !seen.insert(self.current),
+            None => false,
+        };
+        if repeated {
+            self.first_repeat = Some((self.current, self.changes));
+            self.seen = None;
+        }
+        Ok(())
+    }
+
+    fn report(&self) {
+        print!("After {} changes: frequency {} (min {}, max {})",
+               self.changes, self.current, self.min, self.max);
+        match self.first_repeat {
+            Some((freq, changes)) => {
+                println!(", first repeat {} after {} changes", freq, changes);
+            },
+            None => println!(),
+        }
+    }
+}
+
+// Where the frequency first comes back to a value it's been before.
+#[derive(Debug, PartialEq)]
+pub struct Repeat {
+    pub frequency: isize,
+    // How many changes were applied to get there...
+    pub changes: u128,
+    // ...and how many times we started through the list doing it.
+    pub passes: u128,
+}
+
 impl AdventSolver for Solver {
-    fn solve(&mut self) -> Result<(), Error> {
+    fn params(&self) -> &'static [&'static str] {
+        &["stream", "checked", "report_every", "track_repeat"]
+    }
+
+    fn solve(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        let checked = ctx.param("checked", false)?;
+        let stdin = ctx.input_path().to_str() == Some("-");
+        if ctx.param("stream", stdin)? {
+            return Self::solve_stream(ctx, checked);
+        }
         let input_sequence: Vec<isize> =
-            BufReader::new(File::open("input/day01.txt")?)
-                      .lines()
-                      .collect::<Result<Vec<String>, _>>()?
-                      .iter()
-                      .map(|s| s.parse::<isize>())
-                      .collect::<Result<Vec<isize>, _>>()?;
-        Self::solve1(0, &input_sequence);
-        Self::solve2(0, &input_sequence);
+            ctx.input_reader()?
+               .lines()
+               .collect::<Result<Vec<String>, _>>()?
+               .iter()
+               .map(|s| s.parse::<isize>())
+               .collect::<Result<Vec<isize>, _>>()?;
+        let final_frequency = Self::solve1(0, &input_sequence, checked)?;
+        ctx.answer(1, final_frequency);
+        match Self::first_repeat(0, &input_sequence)? {
+            Some(repeat) => {
+                println!("First frequency seen twice: {} (on pass {}, after \
+                          {} changes)",
+                         repeat.frequency, repeat.passes, repeat.changes);
+                ctx.answer(2, repeat.frequency);
+                ctx.explain("part2", Evidence::record()
+                    .with("drift_per_pass", final_frequency)
+                    .with("frequency", repeat.frequency)
+                    .with("passes", repeat.passes)
+                    .with("changes", repeat.changes));
+            },
+            None => {
+                println!("First frequency seen twice: no repeat exists");
+                ctx.answer(2, "none");
+            },
+        }
         Ok(())
     }
 }
 
 impl Solver {
-    fn solve1(initial_frequency: isize, input_sequence: &Vec<isize>) {
-        println!("Final Frequency: {}",
-                 initial_frequency + input_sequence.iter().sum::<isize>());
+    fn solve1(initial_frequency: isize, input_sequence: &Vec<isize>,
+              checked: bool) -> Result<isize, Error> {
+        let mut stats = Stats::new(initial_frequency, checked, false);
+        for &change in input_sequence.iter() {
+            stats.add(change)?;
+        }
+        println!("Final Frequency: {}", stats.current);
+        Ok(stats.current)
+    }
+
+    // Part 1 a line at a time, for input too big to hold (or stdin, which
+    // can't be gone through twice for part 2), in constant memory. Looking
+    // for a repeat within the one pass means remembering every frequency
+    // until one comes round again, so that's only done with track_repeat.
+    fn solve_stream(ctx: &mut Context, checked: bool) -> Result<(), Error> {
+        let report_every = ctx.param("report_every", 0usize)?;
+        let track_repeat = ctx.param("track_repeat", false)?;
+        let mut stats = Stats::new(0, checked, track_repeat);
+        for (i, line) in ctx.input_reader()?.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let change = line.parse::<isize>().map_err(|e| {
+                format_err!("line {}: {}: {}", i + 1, e, line)
+            })?;
+            stats.add(change).map_err(|e| {
+                format_err!("line {}: {}", i + 1, e)
+            })?;
+            if report_every > 0 && stats.changes.is_multiple_of(report_every) {
+                stats.report();
+            }
+        }
+        stats.report();
+        println!("Final Frequency: {}", stats.current);
+        ctx.answer(1, stats.current);
+        ctx.explain("part1", Evidence::record()
+            .with("changes", stats.changes)
+            .with("min", stats.min)
+            .with("max", stats.max));
+        Ok(())
     }
 
-    fn solve2(initial_frequency: isize, input_sequence: &Vec<isize>) {
+    // Finds the first repeat without going round and round the list.
+    //
+    // After k passes plus i more changes, the frequency is p[i] + k*D, where
+    // p[i] is the frequency after i changes on the first pass and D is the
+    // drift over a whole pass. Two of those can only be equal if their p's
+    // are the same modulo D, and then p[i] catches up with a p[j] further
+    // along in the direction of drift after (p[j] - p[i]) / D passes. So
+    // group the p's by residue, sort each group, and the earliest repeat is
+    // the soonest catch-up between neighbours. If no group has two members,
+    // the frequency never repeats.
+    fn first_repeat(initial_frequency: isize, input_sequence: &Vec<isize>)
+            -> Result<Option<Repeat>, Error> {
+        let n = input_sequence.len();
+        if n == 0 {
+            return Ok(None);
+        }
+        let mut prefix = Vec::with_capacity(n);
         let mut freq = initial_frequency;
-        let mut freqs_seen = HashSet::new();
-        for input in input_sequence.iter().cycle() {
-            freq += input;
-            if freqs_seen.contains(&freq) {
-                break;
+        for change in input_sequence.iter() {
+            prefix.push(freq);
+            freq = freq.checked_add(*change)
+                       .ok_or_else(|| format_err!("Frequency overflowed"))?;
+        }
+        let drift = freq as i128 - initial_frequency as i128;
+
+        // A repeat within the first pass beats anything later.
+        let mut seen = HashSet::new();
+        for (i, &freq) in prefix.iter().enumerate() {
+            if !seen.insert(freq) {
+                return Ok(Some(Repeat {
+                    frequency: freq,
+                    changes: i as u128,
+                    passes: 1,
+                }));
             }
-            freqs_seen.insert(freq);
         }
-        println!("First frequency seen twice: {}", freq);
+        if drift == 0 {
+            // Everything on the first pass was different, and the second
+            // pass starts where the first one did.
+            return Ok(Some(Repeat {
+                frequency: initial_frequency,
+                changes: n as u128,
+                passes: 1,
+            }));
+        }
+
+        // Sorting in the direction of drift, frequency i reaches frequency
+        // j after (j - i) / |D| passes.
+        let step = drift.abs();
+        let mut groups: HashMap<i128, Vec<(i128, usize)>> = HashMap::new();
+        for (i, &freq) in prefix.iter().enumerate() {
+            let along = freq as i128 * drift.signum();
+            groups.entry(along.rem_euclid(step))
+                  .or_insert_with(Vec::new)
+                  .push((along, i));
+        }
+        let mut best: Option<Repeat> = None;
+        for group in groups.values_mut() {
+            group.sort();
+            for pair in group.windows(2) {
+                let ((from, i), (to, j)) = (pair[0], pair[1]);
+                let passes = ((to - from) / step) as u128;
+                let changes = passes * n as u128 + i as u128;
+                if best.as_ref().is_none_or(|b| changes < b.changes) {
+                    best = Some(Repeat {
+                        frequency: prefix[j],
+                        changes: changes,
+                        passes: if i == 0 { passes } else { passes + 1 },
+                    });
+                }
+            }
+        }
+        Ok(best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Solver, Stats};
+    use std::collections::HashSet;
+    use std::isize;
+    use util::prop;
+
+    #[test]
+    fn rolling_stats() {
+        let mut stats = Stats::new(0, true, true);
+        for &change in [3, -5, 4, 1, -2, 9].iter() {
+            stats.add(change).unwrap();
+        }
+        // 3, -2, 2, 3, 1, 10
+        assert_eq!((10, -2, 10, 6), (stats.current, stats.min, stats.max,
+                                     stats.changes));
+        assert_eq!(Some((3, 4)), stats.first_repeat);
+        assert!(stats.seen.is_none());
+    }
+
+    #[test]
+    fn overflow_is_reported_when_checked() {
+        let mut stats = Stats::new(isize::MAX - 1, true, false);
+        assert!(stats.add(1).is_ok());
+        assert_eq!("Frequency overflowed: 9223372036854775807 + 1",
+                   stats.add(1).unwrap_err().to_string());
+        let mut stats = Stats::new(isize::MAX, false, false);
+        stats.add(1).unwrap();
+        assert_eq!(isize::MIN, stats.current);
+    }
+
+    fn repeat(changes: &[isize]) -> Option<isize> {
+        Solver::first_repeat(0, &changes.to_vec())
+            .unwrap()
+            .map(|repeat| repeat.frequency)
+    }
+
+    #[test]
+    fn part2_examples() {
+        assert_eq!(Some(2), repeat(&[1, -2, 3, 1]));
+        assert_eq!(Some(0), repeat(&[1, -1]));
+        assert_eq!(Some(10), repeat(&[3, 3, 4, -2, -4]));
+        assert_eq!(Some(5), repeat(&[-6, 3, 8, 5, -6]));
+        assert_eq!(Some(14), repeat(&[7, 7, -2, -7, -4]));
+    }
+
+    #[test]
+    fn no_repeat_exists() {
+        assert_eq!(None, repeat(&[]));
+        assert_eq!(None, repeat(&[1]));
+        assert_eq!(None, repeat(&[3, 3, 3]));
+        assert_eq!(None, repeat(&[-1, 3]));
+    }
+
+    #[test]
+    fn passes_and_changes() {
+        // 0, 1000 | 1, 1001 | ... | 999, 1999 | 1000: back to 1000 right at
+        // the end of the 1000th pass.
+        let repeat = Solver::first_repeat(0, &vec![1000, -999]).unwrap()
+                                                               .unwrap();
+        assert_eq!((1000, 1000, 2000),
+                   (repeat.frequency, repeat.passes, repeat.changes));
+    }
+
+    // The obvious way: go round until something comes back, or give up.
+    fn brute_force(changes: &[isize]) -> Option<isize> {
+        let mut freq = 0;
+        let mut seen = HashSet::new();
+        seen.insert(freq);
+        for change in changes.iter().cycle().take(100_000) {
+            freq += change;
+            if !seen.insert(freq) {
+                return Some(freq);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn agrees_with_brute_force() {
+        // Small changes that mostly cancel out, so that the brute force
+        // finds any repeat within its limit.
+        prop::check("analytic repeat matches going round the list",
+                    |rng, size| {
+                        (0..rng.below(size + 1))
+                            .map(|_| rng.below(21) as isize - 10)
+                            .collect::<Vec<isize>>()
+                    },
+                    prop::shrink_vec,
+                    |changes| {
+                        let drift: isize = changes.iter().sum();
+                        drift.abs() > 2 ||
+                        repeat(changes) == brute_force(changes)
+                    });
+    }
+
+    #[test]
+    fn rolling_stats_match_the_whole_list() {
+        prop::check("streamed stats match the list of frequencies",
+                    |rng, size| {
+                        (0..rng.below(size + 1))
+                            .map(|_| rng.below(21) as isize - 10)
+                            .collect::<Vec<isize>>()
+                    },
+                    prop::shrink_vec,
+                    |changes| {
+                        let mut stats = Stats::new(0, true, true);
+                        let mut freqs = vec![0];
+                        for &change in changes.iter() {
+                            stats.add(change).unwrap();
+                            freqs.push(freqs.last().unwrap() + change);
+                        }
+                        let repeat = (1..freqs.len()).find(|&i| {
+                            freqs[..i].contains(&freqs[i])
+                        });
+                        stats.current == *freqs.last().unwrap() &&
+                        stats.min == *freqs.iter().min().unwrap() &&
+                        stats.max == *freqs.iter().max().unwrap() &&
+                        stats.changes == changes.len() &&
+                        stats.first_repeat == repeat.map(|i| (freqs[i], i))
+                    });
     }
 }
@@ -1,142 +1,354 @@
-use advent::AdventSolver;
+use advent::{AdventSolver, Context};
 use failure::Error;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::str::FromStr;
+use util::evidence::Evidence;
 
 #[derive(Default)]
 pub struct Solver;
 
+// How many times each character appears in an id. Characters are Unicode
+// scalar values, so "é" is one letter however many bytes it takes.
+pub struct Profile {
+    counts: HashMap<char, usize>,
+}
+
+impl Profile {
+    pub fn new(id: &str) -> Profile {
+        let mut counts = HashMap::new();
+        for c in id.chars() {
+            *counts.entry(c).or_insert(0) += 1;
+        }
+        Profile { counts: counts }
+    }
+
+    // Each distinct count, once.
+    pub fn multiplicities(&self) -> HashSet<usize> {
+        self.counts.values().cloned().collect()
+    }
+}
+
+// For each multiplicity, the number of ids with some letter appearing
+// exactly that many times.
+pub type Histogram = BTreeMap<usize, usize>;
+
+// A checksum built from a histogram: the counts for some multiplicities,
+// all multiplied together or all added up. A weight counts a term that many
+// times over, so "2:2*3" is count(2)^2 * count(3). Written as terms joined
+// by '*' or '+', each a multiplicity with an optional ":weight".
+#[derive(Debug, PartialEq)]
+pub struct Formula {
+    terms: Vec<(usize, u32)>,
+    sum: bool,
+}
+
+impl Default for Formula {
+    // The puzzle's: ids with a double letter times ids with a triple.
+    fn default() -> Formula {
+        Formula { terms: vec![(2, 1), (3, 1)], sum: false }
+    }
+}
+
+impl FromStr for Formula {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Formula, Error> {
+        let sum = s.contains('+');
+        if sum && s.contains('*') {
+            return Err(format_err!("Can't mix + and * in a checksum: {}", s));
+        }
+        let terms = s.split(if sum { '+' } else { '*' })
+                     .map(|term| {
+                         let mut parts = term.trim().splitn(2, ':');
+                         let n = parts.next().unwrap().parse::<usize>()?;
+                         let weight = match parts.next() {
+                             Some(weight) => weight.parse::<u32>()?,
+                             None => 1,
+                         };
+                         Ok((n, weight))
+                     })
+                     .collect::<Result<Vec<(usize, u32)>, Error>>()
+                     .map_err(|_| format_err!("Bad checksum formula: {}", s))?;
+        Ok(Formula { terms: terms, sum: sum })
+    }
+}
+
+impl Formula {
+    pub fn apply(&self, histogram: &Histogram) -> Result<usize, Error> {
+        let overflow = || format_err!("Checksum overflowed");
+        let mut total: usize = if self.sum { 0 } else { 1 };
+        for &(n, weight) in self.terms.iter() {
+            let count = *histogram.get(&n).unwrap_or(&0);
+            total = if self.sum {
+                count.checked_mul(weight as usize)
+                     .and_then(|term| total.checked_add(term))
+            } else {
+                count.checked_pow(weight)
+                     .and_then(|term| total.checked_mul(term))
+            }.ok_or_else(overflow)?;
+        }
+        Ok(total)
+    }
+}
+
+// Two ids (indices into the list) within some Hamming distance of each
+// other, and the character positions where they differ.
+#[derive(Debug, PartialEq)]
+pub struct NearPair {
+    pub first: usize,
+    pub second: usize,
+    pub positions: Vec<usize>,
+}
+
+impl NearPair {
+    fn evidence<T: AsRef<str>>(&self, ids: &[T]) -> Evidence {
+        Evidence::record()
+            .with("first", ids[self.first].as_ref())
+            .with("second", ids[self.second].as_ref())
+            .with("positions", self.positions.clone())
+    }
+}
+
 impl AdventSolver for Solver {
-    fn solve(&mut self) -> Result<(), Error> {
+    fn params(&self) -> &'static [&'static str] {
+        &["checksum", "distance", "histogram"]
+    }
+
+    fn solve(&mut self, ctx: &mut Context) -> Result<(), Error> {
         let ids: Vec<String> =
-            BufReader::new(File::open("input/day02.txt")?)
+            BufReader::new(File::open(ctx.input_path())?)
                       .lines()
                       .collect::<Result<Vec<String>, _>>()?;
-        println!("Checksum: {}", Self::checksum(&ids));
+        let formula: Formula = match ctx.param("checksum", String::new())? {
+            ref s if s.is_empty() => Formula::default(),
+            s => s.parse()?,
+        };
+        let histogram = Self::histogram(&ids);
+        let checksum = formula.apply(&histogram)?;
+        println!("Checksum: {}", checksum);
+        ctx.answer(1, checksum);
+        if ctx.param("histogram", false)? {
+            println!("Ids with a letter appearing exactly n times:");
+            for (n, count) in histogram.iter() {
+                println!("{:>4}: {}", n, count);
+            }
+        }
+        if ctx.explaining() {
+            ctx.explain("part1", Evidence::record()
+                .with("histogram", Evidence::list(
+                    histogram.iter().map(|(&n, &count)| (n, count)))));
+        }
         match Self::find_similar_ids(&ids) {
-            Some(result) => println!("Common characters: {}", result),
+            Some(result) => {
+                println!("Common characters: {}", result);
+                ctx.answer(2, result);
+            },
             None => println!("Failed to find similar ids!")
         }
+        if ctx.explaining() {
+            ctx.explain("part2", Evidence::list(
+                Self::near_duplicates(&ids, 1).iter()
+                    .filter(|pair| pair.positions.len() == 1)
+                    .map(|pair| pair.evidence(&ids))));
+        }
+
+        // Optionally, everything within a bigger distance.
+        let distance: Option<usize> = match ctx.param("distance", 0)? {
+            0 => None,
+            k => Some(k),
+        };
+        if let Some(k) = distance {
+            let pairs = Self::near_duplicates(&ids, k);
+            println!("Pairs within distance {}: {}", k, pairs.len());
+            for pair in pairs.iter() {
+                println!("  {} and {} differ at {:?}", ids[pair.first],
+                         ids[pair.second], pair.positions);
+            }
+        }
         Ok(())
     }
 }
 
 impl Solver {
-    // Compute the checksum defined in part 1 of the problem.
-    fn checksum<T: AsRef<str>>(ids: &[T]) -> usize {
-        let count2 = ids.iter()
-                        .filter(|&id| Self::has_exactly_n(id.as_ref(), 2))
-                        .count();
-        let count3 = ids.iter()
-                        .filter(|&id| Self::has_exactly_n(id.as_ref(), 3))
-                        .count();
-        return count2 * count3;
+    // Profiles each id once, and counts up which multiplicities it has.
+    fn histogram<T: AsRef<str>>(ids: &[T]) -> Histogram {
+        let mut histogram = Histogram::new();
+        for id in ids {
+            for n in Profile::new(id.as_ref()).multiplicities() {
+                *histogram.entry(n).or_insert(0) += 1;
+            }
+        }
+        histogram
     }
 
     // Finds the first two ids whose hamming distance is 1, and returns their
     // common characters as a string.
     fn find_similar_ids<T: AsRef<str>>(ids: &[T]) -> Option<String> {
-        for id1 in ids {
-            for id2 in ids {
-                let id1 = id1.as_ref();
-                let id2 = id2.as_ref();
-                if id1.len() != id2.len() {
-                    println!("Bad data, differing lengths: {}, {}", id1, id2);
-                } else if Self::hamming_distance(id1, id2) == 1 {
-                    println!("Found {} and {}.", id1, id2);
-                    return Some(id1.chars().zip(id2.chars())
-                                   .filter(|(c1, c2)| c1 == c2)
-                                   .map(|(c1, _)| c1)
-                                   .collect())
-                    
-                }
+        let pair = Self::near_duplicates(ids, 1)
+                       .into_iter()
+                       .find(|pair| pair.positions.len() == 1)?;
+        let (id1, id2) = (ids[pair.first].as_ref(), ids[pair.second].as_ref());
+        println!("Found {} and {}.", id1, id2);
+        Some(id1.chars()
+                .enumerate()
+                .filter(|&(i, _)| i != pair.positions[0])
+                .map(|(_, c)| c)
+                .collect())
+    }
+
+    // Every pair of ids within Hamming distance k, in order. Ids of
+    // different lengths are never near each other.
+    //
+    // Rather than comparing every pair, cut each id into k+1 blocks. Two ids
+    // with at most k differences can't have one in every block, so they
+    // match exactly on at least one. Index the ids by each of their blocks,
+    // and only compare ids that share one.
+    fn near_duplicates<T: AsRef<str>>(ids: &[T], k: usize) -> Vec<NearPair> {
+        let ids: Vec<Vec<char>> =
+            ids.iter().map(|id| id.as_ref().chars().collect()).collect();
+        let mut index: HashMap<(usize, usize, &[char]), Vec<usize>> =
+            HashMap::new();
+        for (i, id) in ids.iter().enumerate() {
+            let len = id.len();
+            for block in 0..=k {
+                let (start, end) = (block * len / (k+1),
+                                    (block+1) * len / (k+1));
+                index.entry((len, block, &id[start..end]))
+                     .or_insert_with(Vec::new)
+                     .push(i);
             }
         }
-        None
-    }
-
-    // Returns true if the given id contains exactly n of any letter.
-    // n must be a positive value.
-    fn has_exactly_n(id: &str, n: usize) -> bool {
-        assert!(n > 0);
-        let mut sorted_chars: Vec<char> = id.chars().collect();
-        sorted_chars.sort();
-        let mut prev_char: char = 0.into();
-        let mut counter: usize = 0;
-        for c in sorted_chars {
-            if c == prev_char {
-                counter += 1;
-            } else if counter == n {
-                return true;
-            } else {
-                prev_char = c;
-                counter = 1;
+
+        let mut compared = HashSet::new();
+        let mut pairs = Vec::new();
+        for bucket in index.values() {
+            for (n, &i) in bucket.iter().enumerate() {
+                for &j in bucket[n+1..].iter() {
+                    if !compared.insert((i, j)) {
+                        continue;
+                    }
+                    let positions: Vec<usize> =
+                        ids[i].iter().zip(ids[j].iter())
+                              .enumerate()
+                              .filter(|&(_, (a, b))| a != b)
+                              .map(|(pos, _)| pos)
+                              .collect();
+                    if positions.len() <= k {
+                        pairs.push(NearPair {
+                            first: i,
+                            second: j,
+                            positions: positions,
+                        });
+                    }
+                }
             }
         }
-        return counter == n;
-    }
-
-    // Return the number of positions where id1 and id2 have different
-    // characters. The strings must have the same length (panics otherwise).
-    fn hamming_distance(id1: &str, id2: &str) -> usize {
-        assert!(id1.len() == id2.len());
-        id1.chars().zip(id2.chars())
-           .filter(|(c1, c2)| c1 != c2)
-           .count()
+        pairs.sort_by_key(|pair| (pair.first, pair.second));
+        pairs
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Solver;
+    use super::{Formula, NearPair, Profile, Solver};
+    use std::collections::HashMap;
+    use util::prop;
+
+    // The multiplicities of an id's letters, in order.
+    fn multiplicities(id: &str) -> Vec<usize> {
+        let mut multiplicities: Vec<usize> =
+            Profile::new(id).multiplicities().into_iter().collect();
+        multiplicities.sort();
+        multiplicities
+    }
+
+    // Where two ids differ, or None if they're different lengths.
+    fn differences(id1: &str, id2: &str) -> Option<Vec<usize>> {
+        Solver::near_duplicates(&[id1, id2], id1.chars().count())
+            .pop()
+            .map(|pair| pair.positions)
+    }
 
     #[test]
-    #[should_panic]
-    fn has_exactly_n_panics_with_zero_size() {
-        Solver::has_exactly_n("hello world", 0);
+    fn multiplicities_never_include_zero() {
+        assert!(!Profile::new("hello world").multiplicities().contains(&0));
     }
 
     #[test]
-    fn has_exactly_n_always_false_with_empty_string() {
-        assert!(!Solver::has_exactly_n("", 1));
-        assert!(!Solver::has_exactly_n("", 2));
-        assert!(!Solver::has_exactly_n("", 3));
+    fn multiplicities_of_empty_string() {
+        assert!(multiplicities("").is_empty());
     }
 
     #[test]
-    fn has_exactly_n_simple_examples() {
+    fn multiplicities_simple_examples() {
         // One H, two o, three l.
-        assert!( Solver::has_exactly_n("Hello, world!", 1));
-        assert!( Solver::has_exactly_n("Hello, world!", 2));
-        assert!( Solver::has_exactly_n("Hello, world!", 3));
-        assert!(!Solver::has_exactly_n("Hello, world!", 4));
+        assert_eq!(vec![1, 2, 3], multiplicities("Hello, world!"));
+        assert_eq!(vec![3, 5], multiplicities("aaabbbbb"));
+    }
 
-        assert!(!Solver::has_exactly_n("aaabbbbb", 1));
-        assert!(!Solver::has_exactly_n("aaabbbbb", 2));
-        assert!( Solver::has_exactly_n("aaabbbbb", 3));
-        assert!(!Solver::has_exactly_n("aaabbbbb", 4));
-        assert!( Solver::has_exactly_n("aaabbbbb", 5));
-        assert!(!Solver::has_exactly_n("aaabbbbb", 6));
+    #[test]
+    fn differences_need_the_same_length() {
+        assert_eq!(None, differences("hello", "world!"));
+    }
+
+    #[test]
+    fn differences_on_empty_strings() {
+        assert_eq!(Some(vec![]), differences("", ""));
     }
 
     #[test]
-    #[should_panic]
-    fn hamming_distance_panics_if_strings_are_not_the_same_length() {
-        Solver::hamming_distance("hello", "world!");
+    fn differences_simple_examples() {
+        assert_eq!(Some(vec![]), differences("abc", "abc"));
+        assert_eq!(Some(vec![0, 1, 2, 4]), differences("hello", "world"));
+        assert_eq!(Some(vec![0, 1, 2, 3, 4]), differences("abcde", "fghij"));
+        assert_eq!(Some(vec![0]), differences("abcde", "fbcde"));
+        assert_eq!(Some(vec![4]), differences("abcde", "abcdf"));
     }
 
     #[test]
-    fn hamming_distance_on_empty_strings() {
-        assert_eq!(0, Solver::hamming_distance("", ""));
+    fn differences_are_symmetric() {
+        prop::check("differences(a, b) == differences(b, a)",
+                    |rng, size| {
+                        // Two ids of the same length
+                        let a = prop::string_from(rng, size, "abc");
+                        let b = a.chars()
+                                 .map(|c| {
+                                     if rng.below(2) == 0 { c } else { 'a' }
+                                 })
+                                 .collect::<String>();
+                        (a, b)
+                    },
+                    |&(ref a, ref b)| {
+                        // Drop the same position from both
+                        (0..a.len()).map(|i| {
+                            let mut a = a.clone();
+                            let mut b = b.clone();
+                            a.remove(i);
+                            b.remove(i);
+                            (a, b)
+                        }).collect()
+                    },
+                    |&(ref a, ref b)| differences(a, b) == differences(b, a));
     }
 
     #[test]
-    fn hamming_distance_simple_examples() {
-        assert_eq!(0, Solver::hamming_distance("abc", "abc"));
-        assert_eq!(4, Solver::hamming_distance("hello", "world"));
-        assert_eq!(5, Solver::hamming_distance("abcde", "fghij"));
-        assert_eq!(1, Solver::hamming_distance("abcde", "fbcde"));
-        assert_eq!(1, Solver::hamming_distance("abcde", "abcdf"));
+    fn multiplicities_agree_with_letter_counts() {
+        prop::check("multiplicities match a straightforward count",
+                    |rng, size| prop::string_from(rng, size, "abcd"),
+                    prop::shrink_string,
+                    |id| {
+                        let mut counts = HashMap::new();
+                        for c in id.chars() {
+                            *counts.entry(c).or_insert(0) += 1;
+                        }
+                        let multiplicities = Profile::new(id).multiplicities();
+                        (1..5).all(|n| {
+                            multiplicities.contains(&n) ==
+                                counts.values().any(|&count| count == n)
+                        })
+                    });
     }
 
     lazy_static! {
@@ -163,7 +375,33 @@ mod tests {
 
     #[test]
     fn test_part1_example() {
-        assert_eq!(12, Solver::checksum(&EXAMPLE1_IDS));
+        let histogram = Solver::histogram(&EXAMPLE1_IDS);
+        assert_eq!(12, Formula::default().apply(&histogram).unwrap());
+    }
+
+    #[test]
+    fn histogram_and_formulas() {
+        let histogram = Solver::histogram(&EXAMPLE1_IDS);
+        // Every example id but "ababab" has a single letter somewhere.
+        assert_eq!(vec![(1, 6), (2, 4), (3, 3)],
+                   histogram.into_iter().collect::<Vec<_>>());
+        let histogram = Solver::histogram(&EXAMPLE1_IDS);
+        let apply = |formula: &str| {
+            formula.parse::<Formula>().unwrap().apply(&histogram).unwrap()
+        };
+        assert_eq!(12, apply("2*3"));
+        assert_eq!(48, apply("2:2*3"));
+        assert_eq!(7, apply("2+3"));
+        assert_eq!(11, apply("2:2+3"));
+        assert_eq!(0, apply("3*4"));
+        assert!("2*3+4".parse::<Formula>().is_err());
+        assert!("2*x".parse::<Formula>().is_err());
+    }
+
+    #[test]
+    fn profiles_count_unicode_letters() {
+        assert_eq!(vec![1, 2], multiplicities("naïve ïo\u{0}\u{0}"));
+        assert_eq!(Some(vec![2]), differences("naïve", "naive"));
     }
 
     #[test]
@@ -171,4 +409,58 @@ mod tests {
         assert_eq!(Some("fgij".to_string()),
                    Solver::find_similar_ids(&EXAMPLE2_IDS));
     }
+
+    #[test]
+    fn near_duplicates_examples() {
+        assert_eq!(vec![NearPair { first: 1, second: 4, positions: vec![2] }],
+                   Solver::near_duplicates(&EXAMPLE2_IDS, 1));
+        let pairs = Solver::near_duplicates(&EXAMPLE2_IDS, 3);
+        assert!(pairs.contains(&NearPair { first: 0, second: 5,
+                                           positions: vec![1, 3] }));
+        // Different lengths, duplicates, and multi-byte characters.
+        assert_eq!(vec![NearPair { first: 1, second: 3, positions: vec![] },
+                        NearPair { first: 1, second: 4, positions: vec![1] },
+                        NearPair { first: 3, second: 4, positions: vec![1] }],
+                   Solver::near_duplicates(&["ab", "añb", "a", "añb", "aéb"],
+                                           1));
+    }
+
+    #[test]
+    fn near_duplicates_agrees_with_brute_force() {
+        prop::check("indexed search finds exactly the close pairs",
+                    |rng, size| {
+                        let k = rng.below(4);
+                        let ids: Vec<String> = (0..rng.below(size + 1))
+                            .map(|_| {
+                                let len = 3 + rng.below(3);
+                                prop::string_from(rng, len, "ab")
+                            })
+                            .collect();
+                        (k, ids)
+                    },
+                    |&(k, ref ids)| {
+                        prop::shrink_vec(ids).into_iter()
+                                             .map(|ids| (k, ids))
+                                             .collect()
+                    },
+                    |&(k, ref ids)| {
+                        let mut expected = Vec::new();
+                        for i in 0..ids.len() {
+                            for j in i+1..ids.len() {
+                                let distance = ids[i].chars()
+                                    .zip(ids[j].chars())
+                                    .filter(|(a, b)| a != b)
+                                    .count();
+                                if ids[i].len() == ids[j].len() &&
+                                   distance <= k {
+                                    expected.push((i, j));
+                                }
+                            }
+                        }
+                        Solver::near_duplicates(ids, k)
+                            .iter()
+                            .map(|pair| (pair.first, pair.second))
+                            .collect::<Vec<_>>() == expected
+                    });
+    }
 }
@@ -1,12 +1,14 @@
-use advent::AdventSolver;
+use advent::{AdventSolver, Context};
 use failure::Error;
-use rand;
 use regex::Regex;
-use std::collections::HashMap;
-use std::fmt;
-use std::fs::File;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::fs::{self, File};
 use std::path::Path;
-use std::io::{BufRead, BufReader};
+use std::io::Read;
+use std::str;
+use util::evidence::Evidence;
+use util::rng::Rng;
 
 lazy_static! {
     static ref CLAIM_RE: Regex =
@@ -16,7 +18,7 @@ lazy_static! {
                    (?P<w>\d+)x(?P<h>\d+)$").unwrap();
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 struct Claim {
     id: usize,
     pos_x: usize,
@@ -36,121 +38,1032 @@ impl Claim {
     fn parse(line: &str) -> Result<Claim, Error> {
         match CLAIM_RE.captures(line) {
             Some(caps) => {
-                Ok(Claim {
+                let claim = Claim {
                     id:     caps["id"].parse::<usize>()?,
                     pos_x:  caps["x"].parse::<usize>()?,
                     pos_y:  caps["y"].parse::<usize>()?,
                     width:  caps["w"].parse::<usize>()?,
                     height: caps["h"].parse::<usize>()?,
-                })
+                };
+                // The far edges have to be representable, or iterating over
+                // the claim's squares overflows.
+                if claim.pos_x.checked_add(claim.width).is_none() ||
+                   claim.pos_y.checked_add(claim.height).is_none() {
+                    return Err(format_err!("Claim out of range: {}", line));
+                }
+                Ok(claim)
             },
             None => Err(format_err!("Couldn't parse claim: {}", line)),
         }
     }
 
-    fn squares_covered(&self) -> Vec<(usize, usize)> {
-        let mut result = Vec::new();
-        for i in self.pos_x..self.pos_x+self.width {
-            for j in self.pos_y..self.pos_y+self.height {
-                result.push((i, j));
+    fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    // The area this claim shares with another, or None if that's too big
+    // to count.
+    fn overlap(&self, other: &Claim) -> Option<usize> {
+        let shared = |a: usize, a_len: usize, b: usize, b_len: usize| {
+            (a + a_len).min(b + b_len).saturating_sub(a.max(b))
+        };
+        shared(self.pos_x, self.width, other.pos_x, other.width)
+            .checked_mul(shared(self.pos_y, self.height,
+                                other.pos_y, other.height))
+    }
+
+    fn squares_covered<'a>(&'a self)
+            -> impl Iterator<Item=(usize, usize)> + 'a {
+        (self.pos_x..self.pos_x+self.width).flat_map(move |i| {
+            (self.pos_y..self.pos_y+self.height).map(move |j| (i, j))
+        })
+    }
+}
+
+// The fabric square by square, as claims come and go. Each square knows how
+// many claims cover it, and each claim how many of its squares it shares,
+// so the answers stay current without a recount.
+struct Fabric {
+    claims: BTreeMap<usize, Claim>,
+    squares: HashMap<(usize, usize), Square>,
+    // Squares covered by two or more claims.
+    contested: usize,
+    // For each claim, by id, how many of its squares are contested.
+    shared: HashMap<usize, usize>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Square {
+    claims: usize,
+    // Sum of the covering claims' ids, which is the id when there's one.
+    id_sum: usize,
+}
+
+// What's left on a square once a claim comes off it.
+enum Left {
+    Empty,
+    Alone(usize),
+    Shared,
+}
+
+impl Fabric {
+    fn new() -> Fabric {
+        Fabric {
+            claims: BTreeMap::new(),
+            squares: HashMap::new(),
+            contested: 0,
+            shared: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, claim: Claim) -> Result<(), Error> {
+        if self.claims.contains_key(&claim.id) {
+            return Err(format_err!("Claim #{} is already on the fabric",
+                                   claim.id));
+        }
+        let mut shared = 0;
+        for pos in claim.squares_covered() {
+            let square = self.squares.entry(pos)
+                                     .or_insert_with(Square::default);
+            if square.claims == 1 {
+                // Its only claim has company now.
+                *self.shared.get_mut(&square.id_sum).unwrap() += 1;
+                self.contested += 1;
+            }
+            if square.claims > 0 {
+                shared += 1;
+            }
+            square.claims += 1;
+            square.id_sum = square.id_sum.wrapping_add(claim.id);
+        }
+        self.shared.insert(claim.id, shared);
+        self.claims.insert(claim.id, claim);
+        Ok(())
+    }
+
+    fn remove(&mut self, id: usize) -> Result<Claim, Error> {
+        let claim = self.claims.remove(&id).ok_or_else(|| {
+            format_err!("No claim #{} on the fabric", id)
+        })?;
+        self.shared.remove(&id);
+        for pos in claim.squares_covered() {
+            let left_alone = {
+                let square = self.squares.get_mut(&pos).unwrap();
+                square.claims -= 1;
+                square.id_sum = square.id_sum.wrapping_sub(id);
+                match square.claims {
+                    0 => Left::Empty,
+                    1 => Left::Alone(square.id_sum),
+                    _ => Left::Shared,
+                }
+            };
+            match left_alone {
+                Left::Empty => { self.squares.remove(&pos); },
+                Left::Alone(other) => {
+                    *self.shared.get_mut(&other).unwrap() -= 1;
+                    self.contested -= 1;
+                },
+                Left::Shared => {},
             }
         }
-        result
+        Ok(claim)
+    }
+
+    fn contested_area(&self) -> usize {
+        self.contested
+    }
+
+    // Claims that don't share any squares, in order of id.
+    fn intact_claims(&self) -> Vec<&Claim> {
+        self.claims.values()
+                   .filter(|claim| self.shared[&claim.id] == 0)
+                   .collect()
     }
 }
 
-#[derive(PartialEq,Eq)]
-enum SquareState {
-    Empty,
-    SingleCoverage(usize), // Parameter is the id of the claim covering
-    MultipleCoverage,
+// Coverage worked out from the claims' edges instead of their squares, so
+// the cost depends on how many claims there are and not on how big they are.
+struct Coverage {
+    // Squares covered by two or more claims.
+    contested_area: usize,
+    // Whether each claim overlaps any other.
+    contested: Vec<bool>,
+}
+
+// A claim's left or right edge, for sweeping across: (x, +1 at the left edge
+// or -1 at the right, the indices of its top and bottom in the sorted ys,
+// and which claim). Sorted, the right edges at any x come before the left
+// ones, since claims that only touch don't overlap.
+type Edge = (usize, i32, usize, usize, usize);
+
+impl Coverage {
+    fn new(claims: &[Claim]) -> Result<Coverage, Error> {
+        let (ys, edges) = Self::edges(claims);
+        Ok(Coverage {
+            contested_area: Self::contested_area(&ys, &edges)?,
+            contested: Self::contested_claims(claims.len(), &ys, &edges),
+        })
+    }
+
+    // The distinct top and bottom edges of the (non-empty) claims, sorted,
+    // and their left and right edges in the order to sweep across them.
+    fn edges(claims: &[Claim]) -> (Vec<usize>, Vec<Edge>) {
+        let mut ys: Vec<usize> =
+            claims.iter()
+                  .filter(|claim| !claim.is_empty())
+                  .flat_map(|c| vec![c.pos_y, c.pos_y + c.height])
+                  .collect();
+        ys.sort();
+        ys.dedup();
+        let mut edges = Vec::new();
+        for (i, claim) in claims.iter().enumerate() {
+            if claim.is_empty() {
+                continue;
+            }
+            let top = ys.binary_search(&claim.pos_y).unwrap();
+            let bottom = ys.binary_search(&(claim.pos_y + claim.height))
+                           .unwrap();
+            edges.push((claim.pos_x, 1, top, bottom, i));
+            edges.push((claim.pos_x + claim.width, -1, top, bottom, i));
+        }
+        edges.sort();
+        (ys, edges)
+    }
+
+    // Sweeps a line across the fabric from left to right, stopping at each
+    // claim's left and right edges. A segment tree over the claims' top and
+    // bottom edges keeps track of how much of the line is covered twice, so
+    // between stops that's the contested area swept over. O(n log n).
+    fn contested_area(ys: &[usize], edges: &[Edge]) -> Result<usize, Error> {
+        if ys.len() < 2 {
+            return Ok(0);
+        }
+        let overflow = || format_err!("Contested area is too big");
+        let mut tree = CoverTree::new(ys.to_vec());
+        let mut area: usize = 0;
+        let mut prev_x = edges[0].0;
+        for &(x, delta, top, bottom, _) in edges.iter() {
+            area = tree.covered_twice()
+                       .checked_mul(x - prev_x)
+                       .and_then(|swept| area.checked_add(swept))
+                       .ok_or_else(overflow)?;
+            tree.add(top, bottom, delta);
+            prev_x = x;
+        }
+        Ok(area)
+    }
+
+    // The same sweep, marking the claims that overlap another without
+    // finding out which. Claims overlap if they're both on the sweep line
+    // at once and share some of it. So a claim is contested if, when the
+    // line reaches it, it shares the line with a claim already there, or if
+    // any claim that arrives before the line leaves it does. The first is a
+    // count of the claims on the line; the second, of every claim that has
+    // arrived so far, taken when the claim leaves less the same count from
+    // when it arrived. O(n log n).
+    fn contested_claims(num_claims: usize, ys: &[usize], edges: &[Edge])
+            -> Vec<bool> {
+        let mut contested = vec![false; num_claims];
+        let mut on_line = EdgeCounts::new(ys.len());
+        let mut arrived = EdgeCounts::new(ys.len());
+        let mut arrived_before = vec![0; num_claims];
+        for &(_, delta, top, bottom, i) in edges.iter() {
+            if delta > 0 {
+                if on_line.overlapping(top, bottom) > 0 {
+                    contested[i] = true;
+                }
+                arrived_before[i] = arrived.overlapping(top, bottom);
+                on_line.add(top, bottom, 1);
+                arrived.add(top, bottom, 1);
+            } else {
+                on_line.add(top, bottom, -1);
+                // Less one for the claim itself.
+                if arrived.overlapping(top, bottom) - arrived_before[i] > 1 {
+                    contested[i] = true;
+                }
+            }
+        }
+        contested
+    }
+
+    // Claims that don't overlap any other claim.
+    fn uncompromised<'a>(&self, claims: &'a [Claim]) -> Vec<&'a Claim> {
+        claims.iter()
+              .zip(self.contested.iter())
+              .filter(|&(_, &contested)| !contested)
+              .map(|(claim, _)| claim)
+              .collect()
+    }
+}
+
+// Which claims overlap which, and by how much, for asking about conflicts.
+// Claims are referred to by id.
+struct ConflictGraph {
+    ids: Vec<usize>,
+    // Index of each claim's id in `ids`.
+    index: HashMap<usize, usize>,
+    // For each claim, the indices of the claims it overlaps and the area
+    // they share.
+    edges: Vec<Vec<(usize, usize)>>,
+}
+
+impl ConflictGraph {
+    fn new(claims: &[Claim]) -> Result<ConflictGraph, Error> {
+        let mut edges = vec![Vec::new(); claims.len()];
+        for (i, j, area) in Self::overlapping_pairs(claims)? {
+            edges[i].push((j, area));
+            edges[j].push((i, area));
+        }
+        Ok(ConflictGraph {
+            ids: claims.iter().map(|claim| claim.id).collect(),
+            index: claims.iter()
+                         .enumerate()
+                         .map(|(i, claim)| (claim.id, i))
+                         .collect(),
+            edges: edges,
+        })
+    }
+
+    // Pairs of claims (as indices, first < second) that overlap, and the
+    // area they share. Sorts the claims by left edge and sweeps across
+    // them, only comparing each claim with the ones it's still inside of
+    // horizontally, so this is as slow as there are claims side by side.
+    fn overlapping_pairs(claims: &[Claim])
+            -> Result<Vec<(usize, usize, usize)>, Error> {
+        let mut order: Vec<usize> = (0..claims.len())
+            .filter(|&i| !claims[i].is_empty())
+            .collect();
+        order.sort_by_key(|&i| claims[i].pos_x);
+        let mut active: Vec<usize> = Vec::new();
+        let mut pairs = Vec::new();
+        for &i in order.iter() {
+            let claim = &claims[i];
+            active.retain(|&j| {
+                claims[j].pos_x + claims[j].width > claim.pos_x
+            });
+            for &j in active.iter() {
+                let area = claim.overlap(&claims[j]).ok_or_else(|| {
+                    format_err!("Overlap is too big: {} and {}",
+                                claim, claims[j])
+                })?;
+                if area > 0 {
+                    pairs.push((i.min(j), i.max(j), area));
+                }
+            }
+            active.push(i);
+        }
+        pairs.sort();
+        Ok(pairs)
+    }
+
+    // The ids of the claims overlapping the given one, with the shared area.
+    fn overlaps(&self, id: usize) -> Vec<(usize, usize)> {
+        match self.index.get(&id) {
+            Some(&i) => self.edges[i].iter()
+                                     .map(|&(j, area)| (self.ids[j], area))
+                                     .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Groups of claims connected by overlaps, biggest first. Claims that
+    // don't overlap anything aren't in a group.
+    fn groups(&self) -> Vec<Vec<usize>> {
+        let mut seen = vec![false; self.ids.len()];
+        let mut groups = Vec::new();
+        for start in 0..self.ids.len() {
+            if seen[start] || self.edges[start].is_empty() {
+                continue;
+            }
+            seen[start] = true;
+            let mut group = Vec::new();
+            let mut stack = vec![start];
+            while let Some(i) = stack.pop() {
+                group.push(self.ids[i]);
+                for &(j, _) in self.edges[i].iter() {
+                    if !seen[j] {
+                        seen[j] = true;
+                        stack.push(j);
+                    }
+                }
+            }
+            group.sort();
+            groups.push(group);
+        }
+        groups.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+        groups
+    }
+
+    // The claim overlapping the most others, with how many and the total
+    // area it shares. Ties go to the bigger shared area, then the first
+    // claim.
+    fn most_conflicted(&self) -> Option<(usize, usize, usize)> {
+        self.edges.iter()
+                  .enumerate()
+                  .filter(|&(_, edges)| !edges.is_empty())
+                  .map(|(i, edges)| {
+                      (self.ids[i], edges.len(),
+                       edges.iter().map(|&(_, area)| area).sum::<usize>())
+                  })
+                  .max_by(|a, b| {
+                      (a.1, a.2).cmp(&(b.1, b.2)).then(b.0.cmp(&a.0))
+                  })
+    }
+
+    // Graphviz source: one node per claim, and an edge labelled with the
+    // shared area for each overlap.
+    fn to_dot(&self) -> String {
+        let mut dot = String::from("graph conflicts {\n");
+        for (i, &id) in self.ids.iter().enumerate() {
+            writeln!(dot, "  \"#{}\";", id).unwrap();
+            for &(j, area) in self.edges[i].iter().filter(|&&(j, _)| j > i) {
+                writeln!(dot, "  \"#{}\" -- \"#{}\" [label={}];",
+                         id, self.ids[j], area).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// Pixels below the heatmap for its legend, including a small gap.
+const LEGEND_HEIGHT: usize = 12;
+const HIGHLIGHT: [u8; 3] = [0x40, 0xff, 0x40];
+// Bigger than any sensible fabric, small enough to fit in memory.
+const MAX_PIXELS: usize = 1 << 24;
+
+// An RGB picture of the part of the fabric the claims cover (one pixel per
+// square), plus any extra rows asked for below it.
+struct Canvas {
+    left: usize,
+    top: usize,
+    width: usize,
+    // Rows of fabric, and rows in all.
+    rows: usize,
+    height: usize,
+    buf: Vec<u8>,
+}
+
+impl Canvas {
+    fn around(claims: &[Claim], extra_rows: usize) -> Result<Canvas, Error> {
+        let claims: Vec<&Claim> =
+            claims.iter().filter(|claim| !claim.is_empty()).collect();
+        if claims.is_empty() {
+            return Err(format_err!("No claims to draw"));
+        }
+        let left = claims.iter().map(|c| c.pos_x).min().unwrap();
+        let top = claims.iter().map(|c| c.pos_y).min().unwrap();
+        let width = claims.iter().map(|c| c.pos_x + c.width).max().unwrap()
+                    - left;
+        let rows = claims.iter().map(|c| c.pos_y + c.height).max().unwrap()
+                   - top;
+        let height = rows.saturating_add(extra_rows);
+        if width.saturating_mul(height) > MAX_PIXELS {
+            return Err(format_err!("Claims cover {}x{} squares, too many to \
+                                    draw", width, height));
+        }
+        Ok(Canvas {
+            left: left,
+            top: top,
+            width: width,
+            rows: rows,
+            height: height,
+            buf: vec![0; width * height * 3],
+        })
+    }
+
+    // How many claims cover each square of the canvas, row by row, from a
+    // 2D difference array: +1 at a claim's top left corner, -1 at the other
+    // two corners just past its edges, and +1 diagonally past its bottom
+    // right, then summed along rows and columns.
+    fn depths(&self, claims: &[Claim]) -> Vec<usize> {
+        let (w, h) = (self.width + 1, self.rows + 1);
+        let mut diff = vec![0i64; w * h];
+        for claim in claims.iter().filter(|claim| !claim.is_empty()) {
+            let (x0, y0) = (claim.pos_x - self.left, claim.pos_y - self.top);
+            let (x1, y1) = (x0 + claim.width, y0 + claim.height);
+            diff[y0*w + x0] += 1;
+            diff[y0*w + x1] -= 1;
+            diff[y1*w + x0] -= 1;
+            diff[y1*w + x1] += 1;
+        }
+        for y in 0..h {
+            for x in 1..w {
+                diff[y*w + x] += diff[y*w + x - 1];
+            }
+        }
+        for y in 1..h {
+            for x in 0..w {
+                diff[y*w + x] += diff[(y-1)*w + x];
+            }
+        }
+        (0..self.rows).flat_map(|y| diff[y*w..y*w + self.width].to_vec())
+                      .map(|depth| depth as usize)
+                      .collect()
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        let i = (y * self.width + x) * 3;
+        self.buf[i..i+3].copy_from_slice(&color);
+    }
+
+    fn fill(&mut self, claim: &Claim, color: [u8; 3]) {
+        for y in claim.pos_y..claim.pos_y + claim.height {
+            for x in claim.pos_x..claim.pos_x + claim.width {
+                let (x, y) = (x - self.left, y - self.top);
+                self.set(x, y, color);
+            }
+        }
+    }
+
+    fn outline(&mut self, claim: &Claim, color: [u8; 3]) {
+        if claim.is_empty() {
+            return;
+        }
+        let (x0, y0) = (claim.pos_x - self.left, claim.pos_y - self.top);
+        let (x1, y1) = (x0 + claim.width - 1, y0 + claim.height - 1);
+        for x in x0..=x1 {
+            self.set(x, y0, color);
+            self.set(x, y1, color);
+        }
+        for y in y0..=y1 {
+            self.set(x0, y, color);
+            self.set(x1, y, color);
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        image::save_buffer(path, &self.buf, self.width as u32,
+                           self.height as u32, image::RGB(8))?;
+        Ok(())
+    }
+}
+
+// Near black for unclaimed, blue for claimed once, and from yellow to red
+// as overlaps get deeper.
+fn heat_color(depth: usize, max_depth: usize) -> [u8; 3] {
+    match depth {
+        0 => [0x10, 0x10, 0x18],
+        1 => [0x28, 0x46, 0x8c],
+        _ => {
+            let hotter = if max_depth > 2 {
+                (depth - 2) * 0xd0 / (max_depth - 2)
+            } else {
+                0
+            };
+            [0xff, (0xd0 - hotter) as u8, 0x00]
+        },
+    }
+}
+
+// Segment tree over the gaps between sorted y coordinates, counting how
+// many claims cover each stretch of the sweep line.
+struct CoverTree {
+    ys: Vec<usize>,
+    // Claims covering the whole of a node's stretch (and not passed down).
+    count: Vec<i32>,
+    // Length of the node's stretch covered at least once, and twice.
+    once: Vec<usize>,
+    twice: Vec<usize>,
+}
+
+impl CoverTree {
+    fn new(ys: Vec<usize>) -> CoverTree {
+        let nodes = 4 * ys.len();
+        CoverTree {
+            ys: ys,
+            count: vec![0; nodes],
+            once: vec![0; nodes],
+            twice: vec![0; nodes],
+        }
+    }
+
+    fn covered_twice(&self) -> usize {
+        self.twice[0]
+    }
+
+    // Add (or with -1, remove) a claim covering ys[top]..ys[bottom].
+    fn add(&mut self, top: usize, bottom: usize, delta: i32) {
+        let gaps = self.ys.len() - 1;
+        self.update(0, 0, gaps, top, bottom, delta);
+    }
+
+    fn update(&mut self, node: usize, lo: usize, hi: usize,
+              top: usize, bottom: usize, delta: i32) {
+        if bottom <= lo || hi <= top {
+            return;
+        }
+        if top <= lo && hi <= bottom {
+            self.count[node] += delta;
+        } else {
+            let mid = (lo + hi) / 2;
+            self.update(2*node + 1, lo, mid, top, bottom, delta);
+            self.update(2*node + 2, mid, hi, top, bottom, delta);
+        }
+        let length = self.ys[hi] - self.ys[lo];
+        let (below_once, below_twice) = if hi - lo == 1 {
+            (0, 0)
+        } else {
+            (self.once[2*node + 1] + self.once[2*node + 2],
+             self.twice[2*node + 1] + self.twice[2*node + 2])
+        };
+        self.once[node] = if self.count[node] > 0 { length }
+                          else { below_once };
+        self.twice[node] = match self.count[node] {
+            0 => below_twice,
+            1 => below_once,
+            _ => length,
+        };
+    }
+}
+
+// How many of a set of claims overlap a stretch of y, counting their top and
+// bottom edges (as indices into the sorted ys) in Fenwick trees. The claims
+// overlapping top..bottom are the ones whose top is above `bottom`, less
+// those of them whose bottom is at or above `top`.
+struct EdgeCounts {
+    tops: Vec<i32>,
+    bottoms: Vec<i32>,
+}
+
+impl EdgeCounts {
+    fn new(len: usize) -> EdgeCounts {
+        EdgeCounts { tops: vec![0; len + 1], bottoms: vec![0; len + 1] }
+    }
+
+    fn add(&mut self, top: usize, bottom: usize, delta: i32) {
+        Self::add_at(&mut self.tops, top, delta);
+        Self::add_at(&mut self.bottoms, bottom, delta);
+    }
+
+    fn overlapping(&self, top: usize, bottom: usize) -> i32 {
+        Self::count_below(&self.tops, bottom) -
+        Self::count_below(&self.bottoms, top + 1)
+    }
+
+    fn add_at(tree: &mut [i32], index: usize, delta: i32) {
+        let mut i = index + 1;
+        while i < tree.len() {
+            tree[i] += delta;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    // The total at indices below `end`.
+    fn count_below(tree: &[i32], end: usize) -> i32 {
+        let mut total = 0;
+        let mut i = end;
+        while i > 0 {
+            total += tree[i];
+            i -= i & i.wrapping_neg();
+        }
+        total
+    }
 }
 
 #[derive(Default)]
 pub struct Solver;
 
 impl AdventSolver for Solver {
-    fn solve(&mut self) -> Result<(), Error> {
-        let claims = Self::read_claims()?;
-
-        // Uncomment if you want.
-        //Self::write_animation(&claims)?;
-
-        // Build coverage map
-        let mut coverage: HashMap<(usize, usize), SquareState> = HashMap::new();
-        for claim in &claims {
-            for &pos in claim.squares_covered().iter() {
-                let state = coverage.entry(pos).or_insert(SquareState::Empty);
-                *state = match state {
-                    SquareState::Empty =>
-                        SquareState::SingleCoverage(claim.id),
-                    SquareState::SingleCoverage(_) =>
-                        SquareState::MultipleCoverage,
-                    SquareState::MultipleCoverage =>
-                        SquareState::MultipleCoverage,
-                };
-            }
-        }
+    fn params(&self) -> &'static [&'static str] {
+        &["conflicts", "dot", "heatmap", "highlight", "frames", "seed",
+          "without"]
+    }
 
-        // Find squares covered by multiple claims
-        let squares_covered_by_2_or_more_claims =
-            coverage.iter()
-                    .filter(|&(_, state)|
-                            *state == SquareState::MultipleCoverage)
-                    .count();
-        println!("Squares covered by 2 or more claims: {}",
-                 squares_covered_by_2_or_more_claims);
+    fn solve(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        let claims = Self::read_claims(&ctx.input_path())?;
 
-        // Find the only claim that is uncompromised
-        let uncompromised_claims =
-            claims.iter()
-                  .filter(|claim| {
-                      claim.squares_covered()
-                           .iter()
-                           .all(|pos| coverage[pos] ==
-                                      SquareState::SingleCoverage(claim.id))
-                  });
+        let coverage = Coverage::new(&claims)?;
+        let contested = coverage.contested_area;
+        println!("Squares covered by 2 or more claims: {}", contested);
+        ctx.answer(1, contested);
         println!("Uncompromised claims:");
-        for claim in uncompromised_claims {
+        let uncompromised = coverage.uncompromised(&claims);
+        for claim in uncompromised.iter() {
             println!("{}", claim);
         }
+        ctx.answer(2, uncompromised.iter()
+                                   .map(|claim| claim.id.to_string())
+                                   .collect::<Vec<String>>()
+                                   .join(","));
+
+        // Finding every overlapping pair can be slow when lots of claims sit
+        // side by side, so the graph is only built when it's asked for.
+        let dot_path = ctx.param("dot", String::new())?;
+        if ctx.param("conflicts", false)? || !dot_path.is_empty() ||
+           ctx.explaining() {
+            Self::report_conflicts(ctx, &claims, &dot_path)?;
+        }
+
+        // What if some claims were withdrawn? Ids separated by commas.
+        let without = ctx.param("without", String::new())?;
+        if !without.is_empty() {
+            let mut fabric = Self::fabric(&claims);
+            for id in without.split(',') {
+                fabric.remove(id.trim().parse::<usize>()?)?;
+            }
+            println!("Without claims {}: {} contested squares, intact claims: \
+                      {}", without, fabric.contested_area(),
+                     fabric.intact_claims()
+                           .iter()
+                           .map(|claim| format!("#{}", claim.id))
+                           .collect::<Vec<String>>()
+                           .join(", "));
+        }
+
+        let heatmap = ctx.param("heatmap", String::new())?;
+        if !heatmap.is_empty() {
+            let highlight = if ctx.param("highlight", false)? {
+                uncompromised.clone()
+            } else {
+                Vec::new()
+            };
+            let squares = Self::draw_heatmap(&claims, &highlight,
+                                             Path::new(&heatmap))?;
+            println!("Heatmap saved to {}. Legend, left to right:", heatmap);
+            let max_depth = squares.len() - 1;
+            for (depth, count) in squares {
+                let [r, g, b] = heat_color(depth, max_depth);
+                println!("  #{:02x}{:02x}{:02x}  {} claims deep: {} squares",
+                         r, g, b, depth, count);
+            }
+        }
+        let frames = ctx.param("frames", String::new())?;
+        if !frames.is_empty() {
+            let count = Self::draw_frames(&claims, Path::new(&frames),
+                                          ctx.param("seed", 1)?)?;
+            println!("{} frames saved to {}.", count, frames);
+        }
         Ok(())
     }
 }
 
 impl Solver {
-    fn read_claims() -> Result<Vec<Claim>, Error> {
-        BufReader::new(File::open("input/day03.txt")?)
-                  .lines()
-                  .collect::<Result<Vec<String>, _>>()?
-                  .iter()
-                  .map(|line| Claim::parse(line))
-                  .collect()
+    fn read_claims(path: &Path) -> Result<Vec<Claim>, Error> {
+        let mut input = String::new();
+        File::open(path)?.read_to_string(&mut input)?;
+        Self::parse_claims(&input)
     }
 
-    // Hacky animation for funsies
-    #[allow(dead_code)]
-    fn write_animation(claims: &Vec<Claim>) -> Result<(), Error> {
-        let mut buf: [u8;1000*1000*4] = [0;1000*1000*4];
-        for x in 0..1000 {
-            for y in 0..1000 {
-                buf[y*4000+x*4+3] = 0xff;
+    fn parse_claims(input: &str) -> Result<Vec<Claim>, Error> {
+        let claims = input.lines()
+                          .map(|line| Claim::parse(line))
+                          .collect::<Result<Vec<Claim>, Error>>()?;
+        let mut ids = HashSet::new();
+        match claims.iter().find(|claim| !ids.insert(claim.id)) {
+            Some(claim) => Err(format_err!("Duplicate claim id: {}", claim)),
+            None => Ok(claims),
+        }
+    }
+
+    // Conflict groups and the most conflicted claim, and the graph as DOT
+    // if there's somewhere to save it.
+    fn report_conflicts(ctx: &mut Context, claims: &[Claim], dot_path: &str)
+            -> Result<(), Error> {
+        let graph = ConflictGraph::new(claims)?;
+        let groups = graph.groups();
+        println!("Conflict groups: {} (largest has {} claims)", groups.len(),
+                 groups.first().map_or(0, |group| group.len()));
+        if let Some((id, count, area)) = graph.most_conflicted() {
+            println!("Most conflicted: claim #{} overlaps {} others \
+                      (shared area {})", id, count, area);
+            if ctx.explaining() {
+                ctx.explain("most_conflicted", Evidence::record()
+                    .with("id", id)
+                    .with("overlaps", Evidence::list(
+                        graph.overlaps(id).into_iter().map(|(other, area)| {
+                            Evidence::record().with("id", other)
+                                              .with("area", area)
+                        }))));
             }
         }
+        if !dot_path.is_empty() {
+            fs::write(dot_path, graph.to_dot())?;
+            println!("Conflict graph saved to {}.", dot_path);
+        }
+        Ok(())
+    }
 
+    // The fabric square by square, which is slow for big claims but
+    // obviously right. Kept as a check on Coverage.
+    fn fabric(claims: &[Claim]) -> Fabric {
+        let mut fabric = Fabric::new();
         for claim in claims {
-            let r = rand::random::<u8>();
-            let g = rand::random::<u8>();
-            let b = rand::random::<u8>();
-            for (x, y) in claim.squares_covered() {
-                buf[y*4000+x*4] = r;
-                buf[y*4000+x*4+1] = g;
-                buf[y*4000+x*4+2] = b;
-            }
-            image::save_buffer(
-                &Path::new(&format!("imgs/claim{:04}.png", claim.id)),
-                &buf, 1000, 1000, image::RGBA(8))?;
+            // Ids are unique once parsed.
+            fabric.add(claim.clone()).unwrap();
         }
+        fabric
+    }
+
+    // Fuzz target: claim parsing alone.
+    pub fn fuzz_parse(data: &[u8]) -> Result<(), Error> {
+        Self::parse_claims(str::from_utf8(data)?)?;
         Ok(())
     }
+
+    // Fuzz target: parsing plus both parts, checked against the square by
+    // square version. That's one map entry per square, so it's skipped for
+    // inputs that would just measure how fast we run out of memory.
+    pub fn fuzz_solve(data: &[u8]) -> Result<(), Error> {
+        let claims = Self::parse_claims(str::from_utf8(data)?)?;
+        let coverage = Coverage::new(&claims)?;
+        let area = claims.iter()
+                         .map(|c| c.width.saturating_mul(c.height))
+                         .fold(0usize, |acc, a| acc.saturating_add(a));
+        if area <= 1 << 20 {
+            Self::check_coverage(&claims, &coverage);
+        }
+        Ok(())
+    }
+
+    // Panics if Coverage disagrees with the square by square answers.
+    fn check_coverage(claims: &[Claim], coverage: &Coverage) {
+        let fabric = Self::fabric(claims);
+        assert_eq!(fabric.contested_area(), coverage.contested_area);
+        let mut ids: Vec<usize> =
+            coverage.uncompromised(claims).iter().map(|c| c.id).collect();
+        ids.sort();
+        assert_eq!(fabric.intact_claims().iter()
+                                         .map(|c| c.id)
+                                         .collect::<Vec<usize>>(),
+                   ids);
+    }
+
+    // A picture of how deeply the fabric is claimed, cropped to the claims,
+    // with a legend strip along the bottom running from unclaimed on the
+    // left to the deepest overlap on the right. Uncompromised claims can be
+    // outlined.
+    fn draw_heatmap(claims: &[Claim], highlight: &[&Claim], path: &Path)
+            -> Result<Vec<(usize, usize)>, Error> {
+        let mut canvas = Canvas::around(claims, LEGEND_HEIGHT)?;
+        let depths = canvas.depths(claims);
+        let max_depth = depths.iter().cloned().max().unwrap_or(0);
+        let mut squares = vec![0; max_depth + 1];
+        for (i, &depth) in depths.iter().enumerate() {
+            squares[depth] += 1;
+            let (x, y) = (i % canvas.width, i / canvas.width);
+            canvas.set(x, y, heat_color(depth, max_depth));
+        }
+        for claim in highlight {
+            canvas.outline(claim, HIGHLIGHT);
+        }
+        let top = canvas.rows + 2;
+        for x in 0..canvas.width {
+            let depth = x * (max_depth + 1) / canvas.width;
+            for y in top..canvas.height {
+                canvas.set(x, y, heat_color(depth, max_depth));
+            }
+        }
+        canvas.save(path)?;
+        Ok(squares.into_iter().enumerate().collect())
+    }
+
+    // One frame per claim, each adding that claim to the last in a color of
+    // its own. The colors come from `seed`, so the same seed draws the same
+    // frames.
+    fn draw_frames(claims: &[Claim], dir: &Path, seed: u64)
+            -> Result<usize, Error> {
+        fs::create_dir_all(dir)?;
+        let mut canvas = Canvas::around(claims, 0)?;
+        let mut rng = Rng::new(seed);
+        for (i, claim) in claims.iter().enumerate() {
+            let color = [rng.next_u64() as u8,
+                         (rng.next_u64() >> 8) as u8,
+                         (rng.next_u64() >> 16) as u8];
+            canvas.fill(claim, color);
+            canvas.save(&dir.join(format!("frame{:04}.png", i + 1)))?;
+        }
+        Ok(claims.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Canvas, Claim, ConflictGraph, Coverage, Fabric, Solver};
+    use util::prop;
+
+    lazy_static! {
+        static ref EXAMPLE: Vec<Claim> = Solver::parse_claims(
+            "#1 @ 1,3: 4x4\n#2 @ 3,1: 4x4\n#3 @ 5,5: 2x2").unwrap();
+    }
+
+    #[test]
+    fn example() {
+        let coverage = Coverage::new(&EXAMPLE).unwrap();
+        assert_eq!(4, coverage.contested_area);
+        assert_eq!(vec![true, true, false], coverage.contested);
+        let graph = ConflictGraph::new(&EXAMPLE).unwrap();
+        assert_eq!(vec![(2, 4)], graph.overlaps(1));
+        assert_eq!(vec![3], coverage.uncompromised(&EXAMPLE)
+                                    .iter()
+                                    .map(|c| c.id)
+                                    .collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn heatmap_depths() {
+        let canvas = Canvas::around(&EXAMPLE, 3).unwrap();
+        assert_eq!((1, 1, 6, 6, 9),
+                   (canvas.left, canvas.top, canvas.width, canvas.rows,
+                    canvas.height));
+        let depths = canvas.depths(&EXAMPLE);
+        let count = |d| depths.iter().filter(|&&depth| depth == d).count();
+        assert_eq!((4, 28, 4), (count(0), count(1), count(2)));
+        // Row 3 of the fabric is the first with both claims, 1 and 2.
+        assert_eq!(vec![1, 1, 2, 2, 1, 1], depths[2*6..3*6].to_vec());
+    }
+
+    #[test]
+    fn conflict_graph() {
+        let claims = Solver::parse_claims(
+            "#1 @ 0,0: 3x3\n#2 @ 2,2: 3x3\n#3 @ 4,4: 2x2\n\
+             #4 @ 10,10: 2x2\n#5 @ 11,11: 2x2\n#6 @ 20,0: 1x1").unwrap();
+        let graph = ConflictGraph::new(&claims).unwrap();
+        assert_eq!(vec![(1, 1), (3, 1)], graph.overlaps(2));
+        assert_eq!(Vec::<(usize, usize)>::new(), graph.overlaps(6));
+        assert_eq!(vec![vec![1, 2, 3], vec![4, 5]], graph.groups());
+        assert_eq!(Some((2, 2, 2)), graph.most_conflicted());
+        assert_eq!("graph conflicts {\n  \"#1\";\n  \"#1\" -- \"#2\" \
+                    [label=1];\n  \"#2\";\n  \"#2\" -- \"#3\" [label=1];\n  \
+                    \"#3\";\n  \"#4\";\n  \"#4\" -- \"#5\" [label=1];\n  \
+                    \"#5\";\n  \"#6\";\n}\n",
+                   graph.to_dot());
+    }
+
+    #[test]
+    fn huge_claims() {
+        // A million squares on a side is no trouble without the squares.
+        let claims = Solver::parse_claims(
+            "#1 @ 0,0: 1000000x1000000\n\
+             #2 @ 999999,999999: 1000000x1000000\n\
+             #3 @ 500000,0: 1000000x1").unwrap();
+        let coverage = Coverage::new(&claims).unwrap();
+        assert_eq!(1 + 500000, coverage.contested_area);
+        let graph = ConflictGraph::new(&claims).unwrap();
+        assert_eq!(vec![(2, 1), (3, 500000)], graph.overlaps(1));
+    }
+
+    #[test]
+    fn many_claims_side_by_side() {
+        // Long thin strips that all share the same columns, but only one
+        // pair of them overlaps. Comparing every claim with the others on
+        // the sweep line would be 50000^2 / 2 comparisons.
+        let mut input: Vec<String> = (0..50000)
+            .map(|i| format!("#{} @ 0,{}: 1000x1", i + 1, 2*i))
+            .collect();
+        input.push("#50001 @ 999,4: 1x2".to_string());
+        let claims = Solver::parse_claims(&input.join("\n")).unwrap();
+        let coverage = Coverage::new(&claims).unwrap();
+        assert_eq!(1, coverage.contested_area);
+        let contested: Vec<usize> = claims.iter()
+                                          .zip(coverage.contested.iter())
+                                          .filter(|&(_, &c)| c)
+                                          .map(|(claim, _)| claim.id)
+                                          .collect();
+        assert_eq!(vec![3, 50001], contested);
+        assert_eq!(49999, coverage.uncompromised(&claims).len());
+    }
+
+    #[test]
+    fn agrees_with_square_by_square_coverage() {
+        prop::check("sweep line matches the coverage map",
+                    |rng, size| {
+                        (0..rng.below(size + 1))
+                            .map(|id| Claim {
+                                id: id + 1,
+                                pos_x: rng.below(20),
+                                pos_y: rng.below(20),
+                                width: rng.below(8),
+                                height: rng.below(8),
+                            })
+                            .collect::<Vec<Claim>>()
+                    },
+                    prop::shrink_vec,
+                    |claims| {
+                        let coverage = Coverage::new(claims).unwrap();
+                        let fabric = Solver::fabric(claims);
+                        let ids = |claims: Vec<&Claim>| {
+                            claims.iter().map(|c| c.id).collect::<Vec<_>>()
+                        };
+                        fabric.contested_area() == coverage.contested_area &&
+                        ids(fabric.intact_claims()) ==
+                            ids(coverage.uncompromised(claims))
+                    });
+    }
+
+    #[test]
+    fn fabric_add_and_remove() {
+        let mut fabric = Fabric::new();
+        let intact = |fabric: &Fabric| {
+            fabric.intact_claims().iter().map(|c| c.id).collect::<Vec<_>>()
+        };
+        for claim in EXAMPLE.iter() {
+            fabric.add(claim.clone()).unwrap();
+        }
+        assert_eq!((4, vec![3]), (fabric.contested_area(), intact(&fabric)));
+        assert!(fabric.add(EXAMPLE[0].clone()).is_err());
+        fabric.remove(2).unwrap();
+        assert_eq!((0, vec![1, 3]), (fabric.contested_area(), intact(&fabric)));
+        assert!(fabric.remove(2).is_err());
+        fabric.add(EXAMPLE[1].clone()).unwrap();
+        fabric.remove(1).unwrap();
+        fabric.remove(3).unwrap();
+        assert_eq!((0, vec![2]), (fabric.contested_area(), intact(&fabric)));
+        fabric.remove(2).unwrap();
+        assert!(fabric.squares.is_empty());
+    }
+
+    #[test]
+    fn fabric_claim_zero() {
+        let mut fabric = Fabric::new();
+        let intact = |fabric: &Fabric| {
+            fabric.intact_claims().iter().map(|c| c.id).collect::<Vec<_>>()
+        };
+        let claims = Solver::parse_claims(
+            "#0 @ 0,0: 2x2\n#1 @ 1,1: 2x2\n#2 @ 1,0: 1x2").unwrap();
+        for claim in claims.iter() {
+            fabric.add(claim.clone()).unwrap();
+        }
+        assert_eq!((2, vec![]), (fabric.contested_area(), intact(&fabric)));
+        fabric.remove(2).unwrap();
+        assert_eq!((1, vec![]), (fabric.contested_area(), intact(&fabric)));
+        fabric.remove(1).unwrap();
+        assert_eq!((0, vec![0]), (fabric.contested_area(), intact(&fabric)));
+        fabric.add(claims[1].clone()).unwrap();
+        fabric.remove(0).unwrap();
+        assert_eq!((0, vec![1]), (fabric.contested_area(), intact(&fabric)));
+    }
+
+    #[test]
+    fn fabric_keeps_up_with_changes() {
+        // Random adds and removes, checked against starting from scratch.
+        prop::check("incremental fabric matches a fresh coverage",
+                    |rng, size| {
+                        (0..rng.below(size + 1))
+                            .map(|_| (rng.below(12), Claim {
+                                id: rng.below(12),
+                                pos_x: rng.below(10),
+                                pos_y: rng.below(10),
+                                width: rng.below(5),
+                                height: rng.below(5),
+                            }))
+                            .collect::<Vec<(usize, Claim)>>()
+                    },
+                    prop::shrink_vec,
+                    |steps| {
+                        let mut fabric = Fabric::new();
+                        for &(remove, ref claim) in steps.iter() {
+                            if fabric.add(claim.clone()).is_err() {
+                                fabric.remove(claim.id).unwrap();
+                            }
+                            let _ = fabric.remove(remove);
+                        }
+                        let claims: Vec<Claim> =
+                            fabric.claims.values().cloned().collect();
+                        let coverage = Coverage::new(&claims).unwrap();
+                        fabric.contested_area() == coverage.contested_area &&
+                        fabric.intact_claims().len() ==
+                            coverage.uncompromised(&claims).len()
+                    });
+    }
 }
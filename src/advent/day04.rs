@@ -1,9 +1,14 @@
-use advent::AdventSolver;
+use advent::{AdventSolver, Context};
 use failure::Error;
 use regex::Regex;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::str;
+use util::evidence::Evidence;
 
 #[derive(Default)]
 pub struct Solver;
@@ -11,15 +16,125 @@ pub struct Solver;
 lazy_static! {
     static ref SHIFT_ENTRY_REGEX: Regex = Regex::new(
         r"(?x)
-          :(?P<min>\d\d)\]\s
+          ^\[(?P<year>\d{4})-(?P<month>\d\d)-(?P<day>\d\d)\s
+             (?P<hour>\d\d):(?P<min>\d\d)\]\s
           (?P<log>
             Guard\s\#(?P<guard>\d+)\sbegins\sshift
            |falls\sasleep
-           |wakes\sup)").unwrap();
+           |wakes\sup)\s*$").unwrap();
+}
+
+// A day in the (proleptic) Gregorian calendar.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Date {
+    year: i32,
+    month: u8,
+    day: u8,
+}
+
+impl Date {
+    fn new(year: i32, month: u8, day: u8) -> Option<Date> {
+        if !(1..=12).contains(&month) ||
+           !(1..=Self::days_in_month(year, month)).contains(&day) {
+            return None;
+        }
+        Some(Date { year: year, month: month, day: day })
+    }
+
+    fn days_in_month(year: i32, month: u8) -> u8 {
+        match month {
+            2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    fn next(&self) -> Date {
+        if self.day < Self::days_in_month(self.year, self.month) {
+            Date { day: self.day + 1, ..*self }
+        } else if self.month < 12 {
+            Date { month: self.month + 1, day: 1, ..*self }
+        } else {
+            Date { year: self.year + 1, month: 1, day: 1 }
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+// A log entry's time. Orders chronologically.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Timestamp {
+    date: Date,
+    hour: u8,
+    minute: u8,
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:02}:{:02}", self.date, self.hour, self.minute)
+    }
+}
+
+#[derive(Debug)]
+enum Event {
+    BeginsShift(usize),
+    FallsAsleep,
+    WakesUp,
+}
+
+#[derive(Debug)]
+struct LogEntry {
+    // Where it was in the input (from 1), since the log gets sorted.
+    line: usize,
+    time: Timestamp,
+    event: Event,
+}
+
+// Where build_shifts has got to in the log: before the first shift, or
+// with the guard on duty awake, or asleep since the given entry.
+#[derive(Clone, Copy)]
+enum State<'a> {
+    OffDuty,
+    Awake,
+    Asleep(&'a LogEntry),
+}
+
+// One guard's shift, by the date of the midnight hour it covers (shifts
+// can start the evening before). Naps are [start, end) minutes of that hour.
+struct Shift {
+    date: Date,
+    guard: usize,
+    naps: Vec<(u8, u8)>,
+}
+
+impl Shift {
+    // Which minutes of the hour the guard was asleep.
+    fn asleep(&self) -> [bool; 60] {
+        let mut asleep = [false; 60];
+        for &(start, end) in self.naps.iter() {
+            for m in start..end {
+                asleep[m as usize] = true;
+            }
+        }
+        asleep
+    }
+
+    fn minutes_asleep(&self) -> usize {
+        self.asleep().iter().filter(|&&a| a).count()
+    }
 }
 
 struct Guard {
     id: usize,
+    // Minutes asleep on each shift worked, in order.
+    nightly: Vec<u32>,
+    naps: Vec<(u8, u8)>,
     sleepy_minutes: [u32; 60],
 }
 
@@ -27,125 +142,893 @@ impl Guard {
     fn new(id: usize) -> Guard {
         Guard {
             id: id,
+            nightly: Vec::new(),
+            naps: Vec::new(),
             sleepy_minutes: [0; 60]
         }
     }
 
     fn sleepiest_minute(&self) -> u8 {
-        self.sleepy_minutes
-            .iter()
-            .enumerate()
-            .max_by_key(|&(_min, count)| count)
-            .unwrap().0 as u8
+        self.sleepiest_minutes()[0]
+    }
+
+    // Every minute the guard was asleep the most often, if there's a tie.
+    fn sleepiest_minutes(&self) -> Vec<u8> {
+        let most = *self.sleepy_minutes.iter().max().unwrap();
+        (0..60).filter(|&m| self.sleepy_minutes[m as usize] == most)
+               .collect()
     }
 
     fn total_sleep_time(&self) -> u32 {
         self.sleepy_minutes.iter().sum()
     }
+
+    // The guard's sleep histogram, for --explain.
+    fn evidence(&self) -> Evidence {
+        let minute = self.sleepiest_minute();
+        Evidence::record()
+            .with("guard", self.id)
+            .with("total_minutes_asleep", self.total_sleep_time())
+            .with("sleepiest_minute", minute)
+            .with("times_asleep_that_minute",
+                  self.sleepy_minutes[minute as usize])
+            .with("times_asleep_by_minute", self.sleepy_minutes.to_vec())
+    }
+}
+
+// A way of picking out a guard to sneak past. Each guard it applies to gets
+// a rating, and the highest rated guards win.
+trait Strategy {
+    fn name(&self) -> &'static str;
+    // What the score means, for reports.
+    fn measure(&self) -> &'static str { "score" }
+    fn rate(&self, guard: &Guard) -> Option<Rating>;
 }
 
+// How a strategy rates a guard: a score, and the stretches of minutes
+// ([start, end)) it points to, more than one if they're equally good.
 #[derive(Debug)]
-enum ShiftEntry {
-    ShiftStart { guard_id: usize },
-    FallsAsleep { guard_id: usize, minute: u8 },
-    WakesUp { guard_id: usize, minute: u8 },
+struct Rating {
+    score: Score,
+    minutes: Vec<(u8, u8)>,
+}
+
+impl Rating {
+    fn at_minutes(score: Score, minutes: Vec<u8>) -> Rating {
+        Rating {
+            score: score,
+            minutes: minutes.into_iter().map(|m| (m, m + 1)).collect(),
+        }
+    }
+}
+
+// An exact fraction, so that ties are really ties. Higher is better,
+// unless it says otherwise.
+#[derive(Clone, Copy, Debug)]
+struct Score {
+    num: i64,
+    den: i64,
+    lower_is_better: bool,
+}
+
+impl Score {
+    fn whole(n: i64) -> Score {
+        Score { num: n, den: 1, lower_is_better: false }
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Score) -> Ordering {
+        let order = (self.num as i128 * other.den as i128)
+            .cmp(&(other.num as i128 * self.den as i128));
+        if self.lower_is_better { order.reverse() } else { order }
+    }
+}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Score) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Score) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.num % self.den == 0 {
+            write!(f, "{}", self.num / self.den)
+        } else {
+            write!(f, "{:.2}", self.num as f64 / self.den as f64)
+        }
+    }
+}
+
+// Every guard a strategy rated highest.
+struct Verdict<'a> {
+    measure: &'static str,
+    winners: Vec<(&'a Guard, Rating)>,
+}
+
+impl<'a> Verdict<'a> {
+    // More than one guard, or more than one stretch of minutes.
+    fn is_tie(&self) -> bool {
+        self.winners.len() > 1 || self.winners[0].1.minutes.len() > 1
+    }
+}
+
+impl<'a> fmt::Display for Verdict<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.winners.len() > 1 {
+            write!(f, "{}-way tie at {} {}: ", self.winners.len(),
+                   self.measure, self.winners[0].1.score)?;
+        } else {
+            write!(f, "{} {}: ", self.measure, self.winners[0].1.score)?;
+        }
+        for (i, &(guard, ref rating)) in self.winners.iter().enumerate() {
+            let minutes = rating.minutes
+                .iter()
+                .map(|&(start, end)| if end == start + 1 {
+                    format!("{}", start)
+                } else {
+                    format!("{}-{}", start, end - 1)
+                })
+                .collect::<Vec<String>>();
+            write!(f, "{}guard #{} (minute{} {})",
+                   if i > 0 { ", " } else { "" }, guard.id,
+                   if minutes.len() > 1 || rating.minutes[0].1 -
+                      rating.minutes[0].0 > 1 { "s" } else { "" },
+                   minutes.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+const STRATEGIES: [&str; 5] = ["most-asleep", "most-frequent-minute",
+                               "longest-nap", "most-consistent",
+                               "best-range"];
+
+// A built-in strategy by name. `width` is for best-range.
+fn strategy(name: &str, width: u8) -> Result<Box<dyn Strategy>, Error> {
+    match name {
+        "most-asleep" => Ok(Box::new(MostAsleep)),
+        "most-frequent-minute" => Ok(Box::new(MostFrequentMinute)),
+        "longest-nap" => Ok(Box::new(LongestNap)),
+        "most-consistent" => Ok(Box::new(MostConsistent)),
+        "best-range" if (1..=60).contains(&width) => {
+            Ok(Box::new(BestRange { width: width }))
+        },
+        "best-range" => Err(format_err!("range_width must be 1 to 60")),
+        _ => Err(format_err!("Unknown strategy: {} (expected one of: {})",
+                             name, STRATEGIES.join(", "))),
+    }
+}
+
+// Part 1: the most minutes asleep in total, at their sleepiest minute.
+struct MostAsleep;
+
+impl Strategy for MostAsleep {
+    fn name(&self) -> &'static str { "most-asleep" }
+
+    fn rate(&self, guard: &Guard) -> Option<Rating> {
+        match guard.total_sleep_time() {
+            0 => None,
+            total => Some(Rating::at_minutes(Score::whole(total as i64),
+                                             guard.sleepiest_minutes())),
+        }
+    }
+}
+
+// Part 2: asleep on the same minute the most often.
+struct MostFrequentMinute;
+
+impl Strategy for MostFrequentMinute {
+    fn name(&self) -> &'static str { "most-frequent-minute" }
+
+    fn rate(&self, guard: &Guard) -> Option<Rating> {
+        match guard.sleepy_minutes[guard.sleepiest_minute() as usize] {
+            0 => None,
+            times => Some(Rating::at_minutes(Score::whole(times as i64),
+                                             guard.sleepiest_minutes())),
+        }
+    }
+}
+
+// The longest single nap, and when it was. Falling asleep and waking in the
+// same minute isn't a nap.
+struct LongestNap;
+
+impl Strategy for LongestNap {
+    fn name(&self) -> &'static str { "longest-nap" }
+
+    fn rate(&self, guard: &Guard) -> Option<Rating> {
+        let longest = guard.naps
+                           .iter()
+                           .map(|&(s, e)| e - s)
+                           .filter(|&length| length > 0)
+                           .max()?;
+        let mut naps = guard.naps
+                            .iter()
+                            .cloned()
+                            .filter(|&(s, e)| e - s == longest)
+                            .collect::<Vec<(u8, u8)>>();
+        naps.sort();
+        naps.dedup();
+        Some(Rating { score: Score::whole(longest as i64), minutes: naps })
+    }
+}
+
+// Whoever sleeps most nearly the same amount every shift: the lowest
+// variance in minutes asleep per shift. Only guards who've slept, over at
+// least two shifts, count.
+struct MostConsistent;
+
+impl Strategy for MostConsistent {
+    fn name(&self) -> &'static str { "most-consistent" }
+
+    fn measure(&self) -> &'static str { "variance" }
+
+    fn rate(&self, guard: &Guard) -> Option<Rating> {
+        let n = guard.nightly.len() as i64;
+        let sum = guard.nightly.iter().map(|&m| m as i64).sum::<i64>();
+        if n < 2 || sum == 0 {
+            return None;
+        }
+        let squares = guard.nightly
+                           .iter()
+                           .map(|&m| (m as i64) * (m as i64))
+                           .sum::<i64>();
+        let variance = Score {
+            num: n * squares - sum * sum,
+            den: n * n,
+            lower_is_better: true,
+        };
+        Some(Rating::at_minutes(variance, guard.sleepiest_minutes()))
+    }
+}
+
+// The `width` minutes in a row the guard was asleep for most often, all
+// shifts added together.
+struct BestRange {
+    width: u8,
+}
+
+impl Strategy for BestRange {
+    fn name(&self) -> &'static str { "best-range" }
+
+    fn rate(&self, guard: &Guard) -> Option<Rating> {
+        let width = self.width as usize;
+        let sums = guard.sleepy_minutes
+                        .windows(width)
+                        .map(|window| window.iter().sum::<u32>())
+                        .collect::<Vec<u32>>();
+        let best = *sums.iter().max()?;
+        if best == 0 {
+            return None;
+        }
+        let minutes = sums.iter()
+                          .enumerate()
+                          .filter(|&(_, &sum)| sum == best)
+                          .map(|(start, _)| {
+                              (start as u8, (start + width) as u8)
+                          })
+                          .collect();
+        Some(Rating { score: Score::whole(best as i64), minutes: minutes })
+    }
 }
 
 impl AdventSolver for Solver {
-    fn solve(&mut self) -> Result<(), Error> {
-        let guards = Self::load_guard_data()?;
-
-        // Part 1: Guard who sleeps the most.
-        let sleepiest_guard =
-            guards.iter()
-                  .max_by_key(|&guard| guard.total_sleep_time())
-                  .unwrap();
-        println!("Part 1: Guard {}'s sleepiest minute: {}",
-                 sleepiest_guard.id,
-                 sleepiest_guard.sleepiest_minute());
-
-        // Part 2: Guard who sleeps the most at a particular minute.
-        let better_target =
-            guards.iter()
-                  .map(|guard| {
-                      let m = guard.sleepiest_minute();
-                      (guard, m, guard.sleepy_minutes[m as usize])
-                  })
-                  .max_by_key(|&(_guard, _min, count)| count)
-                  .unwrap().0;
-        println!("Part 2: Guard {}'s sleepiest minute: {}",
-                 better_target.id, better_target.sleepiest_minute());
+    fn params(&self) -> &'static [&'static str] {
+        &["chart", "csv", "strategy", "range_width"]
+    }
+
+    fn solve(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        let shifts = Self::build_shifts(&Self::read_log(&ctx.input_path())?)?;
+        let guards = Self::tally_guards(&shifts);
+
+        if ctx.param("chart", false)? {
+            print!("{}", Self::chart(&shifts));
+            println!();
+            for guard in guards.iter() {
+                println!("Guard #{}: {} shifts, {} minutes asleep",
+                         guard.id, guard.nightly.len(),
+                         guard.total_sleep_time());
+            }
+        }
+        let strategies = ctx.param("strategy", String::new())?;
+        if !strategies.is_empty() {
+            let width = ctx.param("range_width", 15u8)?;
+            for name in strategies.split(',') {
+                let strategy = strategy(name.trim(), width)?;
+                match Self::judge(&*strategy, &guards) {
+                    Some(verdict) => {
+                        println!("{}: {}", strategy.name(), verdict);
+                    },
+                    None => println!("{}: no guard qualifies",
+                                     strategy.name()),
+                }
+            }
+        }
+        let csv_path = ctx.param("csv", String::new())?;
+        if !csv_path.is_empty() {
+            fs::write(&csv_path, Self::to_csv(&shifts))?;
+            println!("Shifts saved to {}.", csv_path);
+        }
+
+        // Part 1: Guard who sleeps the most. Part 2: Guard who sleeps the
+        // most at a particular minute.
+        let parts: [&dyn Strategy; 2] = [&MostAsleep, &MostFrequentMinute];
+        for (i, &strategy) in parts.iter().enumerate() {
+            let part = i + 1;
+            let verdict = Self::decide(strategy, &guards)?;
+            let guard = verdict.winners[0].0;
+            if verdict.is_tie() {
+                println!("Part {} has no single answer ({}), so going with \
+                          the first", part, verdict);
+            }
+            println!("Part {}: Guard {}'s sleepiest minute: {}",
+                     part, guard.id, guard.sleepiest_minute());
+            ctx.answer(part, guard.id * guard.sleepiest_minute() as usize);
+            if ctx.explaining() {
+                let mut evidence = guard.evidence();
+                if verdict.is_tie() {
+                    evidence = evidence.with("tie", verdict.to_string());
+                }
+                ctx.explain(&format!("part{}", part), evidence);
+            }
+        }
 
         Ok(())
     }
 }
 
 impl Solver {
-    fn load_guard_data() -> Result<Vec<Guard>, Error> {
-        let mut guards = HashMap::new();
-        let shifts = Self::read_shifts()?;
-        let mut fell_asleep: u8 = 0;
-        for shift_line in shifts {
-            match shift_line {
-                ShiftEntry::ShiftStart { guard_id: _ } => {
+    // The verdict a puzzle answer comes from. The puzzle promises there's
+    // just one guard and minute, but if there's a tie anyway the answer is
+    // the first winner (the lowest id) at their earliest minute, and the
+    // verdict says what else it could have been.
+    fn decide<'a>(strategy: &dyn Strategy, guards: &'a [Guard])
+            -> Result<Verdict<'a>, Error> {
+        Self::judge(strategy, guards).ok_or_else(|| {
+            format_err!("No guard ever fell asleep")
+        })
+    }
+
+    // Rates every guard, and keeps all of those with the best score.
+    fn judge<'a>(strategy: &dyn Strategy, guards: &'a [Guard])
+            -> Option<Verdict<'a>> {
+        let mut winners: Vec<(&Guard, Rating)> = Vec::new();
+        for guard in guards {
+            let rating = match strategy.rate(guard) {
+                Some(rating) => rating,
+                None => continue,
+            };
+            let best = winners.first().map(|&(_, ref r)| r.score);
+            match best.map(|best| rating.score.cmp(&best)) {
+                Some(Ordering::Less) => continue,
+                Some(Ordering::Greater) => winners.clear(),
+                _ => {},
+            }
+            winners.push((guard, rating));
+        }
+        if winners.is_empty() {
+            None
+        } else {
+            Some(Verdict { measure: strategy.measure(), winners: winners })
+        }
+    }
+
+    // Every guard who worked a shift, whether or not they slept, by id.
+    fn tally_guards(shifts: &[Shift]) -> Vec<Guard> {
+        let mut guards = BTreeMap::new();
+        for shift in shifts {
+            let guard = guards.entry(shift.guard)
+                              .or_insert(Guard::new(shift.guard));
+            guard.nightly.push(shift.minutes_asleep() as u32);
+            guard.naps.extend(shift.naps.iter().cloned());
+            for (m, &asleep) in shift.asleep().iter().enumerate() {
+                if asleep {
+                    guard.sleepy_minutes[m] += 1;
+                }
+            }
+        }
+        guards.into_values().collect()
+    }
+
+    // The chart from the puzzle statement: a row per shift, with the date,
+    // the guard, and a # for each minute asleep or a . for awake.
+    fn chart(shifts: &[Shift]) -> String {
+        let id_width = shifts.iter()
+                             .map(|shift| format!("#{}", shift.guard).len())
+                             .max()
+                             .unwrap_or(2);
+        let indent = " ".repeat(7 + id_width + 2);
+        let mut chart = format!("{:<7}{:<width$}Minute\n", "Date", "ID",
+                                width = id_width + 2);
+        chart += &indent;
+        chart.extend((0..60).map(|m| (b'0' + m / 10) as char));
+        chart += "\n";
+        chart += &indent;
+        chart.extend((0..60).map(|m| (b'0' + m % 10) as char));
+        chart += "\n";
+        for shift in shifts {
+            chart += &format!("{:02}-{:02}  {:<width$}  ",
+                              shift.date.month, shift.date.day,
+                              format!("#{}", shift.guard), width = id_width);
+            chart.extend(shift.asleep()
+                              .iter()
+                              .map(|&asleep| if asleep { '#' } else { '.' }));
+            chart += "\n";
+        }
+        chart
+    }
+
+    // The same as the chart, with a 1 for each minute asleep.
+    fn to_csv(shifts: &[Shift]) -> String {
+        let mut csv = "date,guard,minutes_asleep".to_string();
+        for m in 0..60 {
+            csv += &format!(",{:02}", m);
+        }
+        csv += "\n";
+        for shift in shifts {
+            csv += &format!("{},{},{}", shift.date, shift.guard,
+                            shift.minutes_asleep());
+            for &asleep in shift.asleep().iter() {
+                csv += if asleep { ",1" } else { ",0" };
+            }
+            csv += "\n";
+        }
+        csv
+    }
+
+    // Groups the (sorted) log into shifts, walking through it as a state
+    // machine and refusing anything that doesn't make sense: sleeping or
+    // waking before the first shift, falling asleep twice, waking without
+    // having slept, a new shift while the last guard is asleep, or a guard
+    // left asleep at the end. The puzzle only charts the midnight hour, so a
+    // nap also has to be inside it: asleep at 00:MM, and awake again by 01:00
+    // the same day.
+    fn build_shifts(log: &[LogEntry]) -> Result<Vec<Shift>, Error> {
+        let mut shifts: Vec<Shift> = Vec::new();
+        let mut state = State::OffDuty;
+        for entry in log {
+            let line = entry.line;
+            state = match (state, &entry.event) {
+                (State::Asleep(since), &Event::BeginsShift(guard)) => {
+                    return Err(format_err!(
+                        "line {}: guard #{} began a shift while guard #{} was \
+                         asleep (since line {})",
+                        line, guard, shifts.last().unwrap().guard,
+                        since.line));
+                },
+                (_, &Event::BeginsShift(guard)) => {
+                    let time = entry.time;
+                    shifts.push(Shift {
+                        date: if time.hour >= 12 { time.date.next() }
+                              else { time.date },
+                        guard: guard,
+                        naps: Vec::new(),
+                    });
+                    State::Awake
+                },
+                (State::OffDuty, &Event::FallsAsleep) => {
+                    return Err(format_err!(
+                        "line {}: fell asleep before any shift began", line));
                 },
-                ShiftEntry::FallsAsleep { guard_id: _, minute } => {
-                    fell_asleep = minute;
+                (State::OffDuty, &Event::WakesUp) => {
+                    return Err(format_err!(
+                        "line {}: woke up before any shift began", line));
                 },
-                ShiftEntry::WakesUp { guard_id, minute } => {
-                    let guard = guards.entry(guard_id)
-                                      .or_insert(Guard::new(guard_id));
-                    for m in fell_asleep..minute {
-                        (*guard).sleepy_minutes[m as usize] += 1;
+                (State::Awake, &Event::FallsAsleep) => State::Asleep(entry),
+                (State::Asleep(since), &Event::FallsAsleep) => {
+                    return Err(format_err!(
+                        "line {}: guard #{} fell asleep again (asleep since \
+                         line {})",
+                        line, shifts.last().unwrap().guard, since.line));
+                },
+                (State::Awake, &Event::WakesUp) => {
+                    return Err(format_err!(
+                        "line {}: guard #{} woke up without falling asleep",
+                        line, shifts.last().unwrap().guard));
+                },
+                (State::Asleep(fell_asleep), &Event::WakesUp) => {
+                    let shift = shifts.last_mut().unwrap();
+                    let (start, end) = (fell_asleep.time, entry.time);
+                    let in_hour = start.date == shift.date && start.hour == 0 &&
+                                  end.date == shift.date &&
+                                  (end.hour == 0 ||
+                                   (end.hour == 1 && end.minute == 0));
+                    if !in_hour {
+                        return Err(format_err!(
+                            "line {}: guard #{} slept from {} to {}, outside \
+                             the midnight hour of {}",
+                            line, shift.guard, start, end, shift.date));
                     }
+                    let end = if end.hour == 1 { 60 } else { end.minute };
+                    shift.naps.push((start.minute, end));
+                    State::Awake
+                },
+            };
+        }
+        if let State::Asleep(since) = state {
+            return Err(format_err!(
+                "line {}: guard #{} was still asleep at the end of the log",
+                since.line, shifts.last().unwrap().guard));
+        }
+        Ok(shifts)
+    }
+
+    fn read_log(path: &Path) -> Result<Vec<LogEntry>, Error> {
+        let mut input = String::new();
+        File::open(path)?.read_to_string(&mut input)?;
+        Self::parse_log(&input)
+    }
+
+    // Parses every line, then puts them in time order. Entries at the same
+    // minute stay in the order they were written.
+    fn parse_log(input: &str) -> Result<Vec<LogEntry>, Error> {
+        let mut log = input.lines()
+                           .enumerate()
+                           .map(|(i, line)| Self::parse_entry(i + 1, line))
+                           .collect::<Result<Vec<LogEntry>, _>>()?;
+        log.sort_by_key(|entry| entry.time);
+        Ok(log)
+    }
+
+    fn parse_entry(line_no: usize, line: &str) -> Result<LogEntry, Error> {
+        let caps = SHIFT_ENTRY_REGEX.captures(line).ok_or_else(|| {
+            format_err!("line {}: couldn't parse: {}", line_no, line)
+        })?;
+        let number = |name: &str| caps[name].parse::<u8>();
+        let time = Date::new(caps["year"].parse::<i32>()?,
+                             number("month")?, number("day")?)
+            .map(|date| Timestamp {
+                date: date,
+                hour: number("hour").unwrap(),
+                minute: number("min").unwrap(),
+            })
+            .filter(|time| time.hour < 24 && time.minute < 60)
+            .ok_or_else(|| {
+                format_err!("line {}: bad timestamp: {}", line_no, line)
+            })?;
+        // Go by which alternative matched rather than the text, since \s
+        // lets tabs and such through.
+        let event = match caps.name("guard") {
+            Some(guard) => Event::BeginsShift(guard.as_str().parse()?),
+            None if caps["log"].starts_with("falls") => Event::FallsAsleep,
+            None => Event::WakesUp,
+        };
+        Ok(LogEntry { line: line_no, time: time, event: event })
+    }
+
+    // Fuzz target: log parsing alone.
+    pub fn fuzz_parse(data: &[u8]) -> Result<(), Error> {
+        Self::parse_log(str::from_utf8(data)?)?;
+        Ok(())
+    }
+
+    // Fuzz target: parsing plus both parts.
+    pub fn fuzz_solve(data: &[u8]) -> Result<(), Error> {
+        let log = Self::parse_log(str::from_utf8(data)?)?;
+        let guards = Self::tally_guards(&Self::build_shifts(&log)?);
+        for name in STRATEGIES.iter() {
+            Self::judge(&*strategy(name, 15)?, &guards);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strategy, Date, Guard, MostAsleep, MostFrequentMinute, Solver,
+                Strategy};
+    use util::prop;
+    use util::rng::Rng;
+
+    const EXAMPLE: &str = "\
+[1518-11-01 00:00] Guard #10 begins shift
+[1518-11-01 00:05] falls asleep
+[1518-11-01 00:25] wakes up
+[1518-11-01 00:30] falls asleep
+[1518-11-01 00:55] wakes up
+[1518-11-01 23:58] Guard #99 begins shift
+[1518-11-02 00:40] falls asleep
+[1518-11-02 00:50] wakes up
+[1518-11-03 00:05] Guard #10 begins shift
+[1518-11-03 00:24] falls asleep
+[1518-11-03 00:29] wakes up
+[1518-11-04 00:02] Guard #99 begins shift
+[1518-11-04 00:36] falls asleep
+[1518-11-04 00:46] wakes up
+[1518-11-05 00:03] Guard #99 begins shift
+[1518-11-05 00:45] falls asleep
+[1518-11-05 00:55] wakes up";
+
+    fn error(log: &str) -> String {
+        Solver::parse_log(log)
+            .and_then(|log| Solver::build_shifts(&log))
+            .err()
+            .map_or(String::new(), |e| e.to_string())
+    }
+
+    #[test]
+    fn example() {
+        let log = Solver::parse_log(EXAMPLE).unwrap();
+        let guards = Solver::tally_guards(&Solver::build_shifts(&log)
+                                                  .unwrap());
+        assert_eq!((10, 24, false), answer(&MostAsleep, &guards));
+        assert_eq!((99, 45, false), answer(&MostFrequentMinute, &guards));
+    }
+
+    #[test]
+    fn shifts_are_dated_by_their_midnight_hour() {
+        // Shuffled, and the 23:58 start belongs to the 2nd.
+        let mut lines: Vec<&str> = EXAMPLE.lines().collect();
+        lines.reverse();
+        let log = Solver::parse_log(&lines.join("\n")).unwrap();
+        let shifts = Solver::build_shifts(&log).unwrap();
+        assert_eq!(vec![("1518-11-01".to_string(), 10),
+                        ("1518-11-02".to_string(), 99),
+                        ("1518-11-03".to_string(), 10),
+                        ("1518-11-04".to_string(), 99),
+                        ("1518-11-05".to_string(), 99)],
+                   shifts.iter()
+                         .map(|s| (s.date.to_string(), s.guard))
+                         .collect::<Vec<_>>());
+        assert_eq!(vec![(5, 25), (30, 55)], shifts[0].naps);
+    }
+
+    #[test]
+    fn chart_matches_the_puzzle() {
+        let log = Solver::parse_log(EXAMPLE).unwrap();
+        let shifts = Solver::build_shifts(&log).unwrap();
+        assert_eq!("\
+Date   ID   Minute
+            000000000011111111112222222222333333333344444444445555555555
+            012345678901234567890123456789012345678901234567890123456789
+11-01  #10  .....####################.....#########################.....
+11-02  #99  ........................................##########..........
+11-03  #10  ........................#####...............................
+11-04  #99  ....................................##########..............
+11-05  #99  .............................................##########.....
+", Solver::chart(&shifts));
+        let csv = Solver::to_csv(&shifts);
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(6, rows.len());
+        assert!(rows[0].starts_with("date,guard,minutes_asleep,00,01,"));
+        assert!(rows[3].starts_with("1518-11-03,10,5,0,0,"));
+    }
+
+    #[test]
+    fn guards_who_never_slept_are_tallied() {
+        let log = Solver::parse_log(&format!(
+            "{}\n[1518-11-06 00:00] Guard #7 begins shift", EXAMPLE)).unwrap();
+        let guards = Solver::tally_guards(&Solver::build_shifts(&log)
+                                                  .unwrap());
+        assert_eq!(vec![(7, 1, 0), (10, 2, 50), (99, 3, 30)],
+                   guards.iter()
+                         .map(|g| (g.id, g.nightly.len(), g.total_sleep_time()))
+                         .collect::<Vec<_>>());
+        assert_eq!((10, 24, false), answer(&MostAsleep, &guards));
+        let nobody = Solver::tally_guards(&Solver::build_shifts(
+            &Solver::parse_log("[1518-11-06 00:00] Guard #7 begins shift")
+                .unwrap()).unwrap());
+        assert_eq!(1, nobody.len());
+        assert!(Solver::decide(&MostAsleep, &nobody).is_err());
+        assert!(Solver::decide(&MostFrequentMinute, &nobody).is_err());
+    }
+
+    // The guard and minute a puzzle answer comes from, and whether it was a
+    // tie.
+    fn answer(strategy: &dyn Strategy, guards: &[Guard]) -> (usize, u8, bool) {
+        let verdict = Solver::decide(strategy, guards).unwrap();
+        let guard = verdict.winners[0].0;
+        (guard.id, guard.sleepiest_minute(), verdict.is_tie())
+    }
+
+    fn verdict(name: &str, log: &str) -> String {
+        let log = Solver::parse_log(log).unwrap();
+        let guards = Solver::tally_guards(&Solver::build_shifts(&log)
+                                                  .unwrap());
+        Solver::judge(&*strategy(name, 5).unwrap(), &guards)
+            .map_or("none".to_string(), |v| v.to_string())
+    }
+
+    #[test]
+    fn strategies() {
+        assert_eq!("score 50: guard #10 (minute 24)",
+                   verdict("most-asleep", EXAMPLE));
+        assert_eq!("score 3: guard #99 (minute 45)",
+                   verdict("most-frequent-minute", EXAMPLE));
+        assert_eq!("score 25: guard #10 (minutes 30-54)",
+                   verdict("longest-nap", EXAMPLE));
+        // #10 slept 45 then 5 minutes; #99 10 every night.
+        assert_eq!("variance 0: guard #99 (minute 45)",
+                   verdict("most-consistent", EXAMPLE));
+        assert_eq!("score 11: guard #99 (minutes 41-45, 42-46, 43-47, 44-48, \
+                    45-49)",
+                   verdict("best-range", EXAMPLE));
+        assert_eq!("none", verdict("longest-nap",
+                                   "[1518-11-01 00:00] Guard #7 begins shift"));
+        assert_eq!("none", verdict("longest-nap",
+                                   "[1518-11-01 00:00] Guard #7 begins shift\n\
+                                    [1518-11-01 00:00] falls asleep\n\
+                                    [1518-11-01 00:00] wakes up"));
+        assert_eq!("Unknown strategy: laziest (expected one of: most-asleep, \
+                    most-frequent-minute, longest-nap, most-consistent, \
+                    best-range)",
+                   strategy("laziest", 5).err().unwrap().to_string());
+    }
+
+    #[test]
+    fn ties_are_explicit() {
+        let log = "[1518-11-01 00:00] Guard #10 begins shift
+[1518-11-01 00:05] falls asleep
+[1518-11-01 00:10] wakes up
+[1518-11-02 00:00] Guard #20 begins shift
+[1518-11-02 00:30] falls asleep
+[1518-11-02 00:35] wakes up";
+        assert_eq!("2-way tie at score 5: guard #10 (minutes 5, 6, 7, 8, 9), \
+                    guard #20 (minutes 30, 31, 32, 33, 34)",
+                   verdict("most-asleep", log));
+        // The puzzle's answer goes to the first guard at their first
+        // minute, rather than giving up.
+        let guards = Solver::tally_guards(&Solver::build_shifts(
+            &Solver::parse_log(log).unwrap()).unwrap());
+        assert_eq!((10, 5, true), answer(&MostAsleep, &guards));
+        assert_eq!((10, 5, true), answer(&MostFrequentMinute, &guards));
+
+        // One guard, with no minute slept through more than once.
+        let log = "[1518-11-01 00:00] Guard #10 begins shift
+[1518-11-01 00:05] falls asleep
+[1518-11-01 00:07] wakes up
+[1518-11-02 00:00] Guard #10 begins shift
+[1518-11-02 00:20] falls asleep
+[1518-11-02 00:22] wakes up";
+        assert_eq!("score 1: guard #10 (minutes 5, 6, 20, 21)",
+                   verdict("most-frequent-minute", log));
+        let guards = Solver::tally_guards(&Solver::build_shifts(
+            &Solver::parse_log(log).unwrap()).unwrap());
+        assert_eq!((10, 5, true), answer(&MostFrequentMinute, &guards));
+    }
+
+    #[test]
+    fn invalid_transitions() {
+        let shift = "[1518-11-01 00:00] Guard #10 begins shift\n";
+        assert_eq!("line 1: fell asleep before any shift began",
+                   error("[1518-11-01 00:05] falls asleep"));
+        assert_eq!("line 1: woke up before any shift began",
+                   error("[1518-11-01 00:05] wakes up"));
+        assert_eq!("line 3: guard #10 fell asleep again (asleep since line 2)",
+                   error(&format!("{}[1518-11-01 00:05] falls asleep\n\
+                                   [1518-11-01 00:07] falls asleep", shift)));
+        assert_eq!("line 2: guard #10 woke up without falling asleep",
+                   error(&format!("{}[1518-11-01 00:05] wakes up", shift)));
+        assert_eq!("line 3: guard #99 began a shift while guard #10 was \
+                    asleep (since line 2)",
+                   error(&format!("{}[1518-11-01 00:05] falls asleep\n\
+                                   [1518-11-01 23:58] Guard #99 begins shift",
+                                  shift)));
+        assert_eq!("line 2: guard #10 was still asleep at the end of the log",
+                   error(&format!("{}[1518-11-01 00:05] falls asleep", shift)));
+        // Line numbers are from the input, not the sorted log.
+        assert_eq!("line 1: guard #10 woke up without falling asleep",
+                   error(&format!("[1518-11-01 00:05] wakes up\n{}", shift)));
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(None, Date::new(1518, 2, 29));
+        assert!(Date::new(1600, 2, 29).is_some());
+        assert_eq!(None, Date::new(1900, 2, 29));
+        assert_eq!("1519-01-01",
+                   Date::new(1518, 12, 31).unwrap().next().to_string());
+        assert_eq!("1518-03-01",
+                   Date::new(1518, 2, 28).unwrap().next().to_string());
+        assert_eq!("line 1: bad timestamp: [1518-13-01 00:00] wakes up",
+                   error("[1518-13-01 00:00] wakes up"));
+        assert_eq!("line 1: bad timestamp: [1518-11-01 24:00] wakes up",
+                   error("[1518-11-01 24:00] wakes up"));
+    }
+
+    #[test]
+    fn naps_must_be_in_the_midnight_hour() {
+        let shift = "[1518-11-01 23:58] Guard #10 begins shift\n";
+        assert_eq!("line 3: guard #10 slept from 1518-11-01 23:59 to \
+                    1518-11-02 00:10, outside the midnight hour of 1518-11-02",
+                   error(&format!("{}[1518-11-01 23:59] falls asleep\n\
+                                   [1518-11-02 00:10] wakes up", shift)));
+        assert_eq!("line 3: guard #10 slept from 1518-11-02 00:50 to \
+                    1518-11-02 01:10, outside the midnight hour of 1518-11-02",
+                   error(&format!("{}[1518-11-02 00:50] falls asleep\n\
+                                   [1518-11-02 01:10] wakes up", shift)));
+        assert_eq!("line 3: guard #10 slept from 1518-11-02 00:50 to \
+                    1518-11-03 00:10, outside the midnight hour of 1518-11-02",
+                   error(&format!("{}[1518-11-02 00:50] falls asleep\n\
+                                   [1518-11-03 00:10] wakes up", shift)));
+        // Waking right on 01:00 means sleeping through 00:59.
+        let log = Solver::parse_log(&format!(
+            "{}[1518-11-02 00:50] falls asleep\n\
+             [1518-11-02 01:00] wakes up", shift)).unwrap();
+        assert_eq!(vec![(50, 60)],
+                   Solver::build_shifts(&log).unwrap()[0].naps);
+    }
+
+    // A guard and their naps.
+    type Night = (usize, Vec<(u8, u8)>);
+
+    // Nights from 1518-02-27, so the end of February gets crossed.
+    fn random_nights(rng: &mut Rng, size: usize) -> Vec<Night> {
+        (0..rng.below(size + 1))
+            .map(|_| {
+                let mut minutes: Vec<u8> = (1..=60).collect();
+                for i in (1..minutes.len()).rev() {
+                    let j = rng.below(i + 1);
+                    minutes.swap(i, j);
                 }
+                minutes.truncate(2 * rng.below(4));
+                minutes.sort();
+                let naps = minutes.chunks(2).map(|nap| (nap[0], nap[1]))
+                                  .collect();
+                (1 + rng.below(4), naps)
+            })
+            .collect()
+    }
+
+    // The log for those nights, backwards. A shift starts before midnight
+    // every other night.
+    fn log(nights: &Vec<Night>) -> String {
+        let mut lines = Vec::new();
+        let mut eve = Date::new(1518, 2, 26).unwrap();
+        for (i, &(guard, ref naps)) in nights.iter().enumerate() {
+            let date = eve.next();
+            lines.push(if i % 2 == 0 {
+                format!("[{} 23:58] Guard #{} begins shift", eve, guard)
+            } else {
+                format!("[{} 00:00] Guard #{} begins shift", date, guard)
+            });
+            for &(start, end) in naps.iter() {
+                lines.push(format!("[{} 00:{:02}] falls asleep", date, start));
+                lines.push(if end == 60 {
+                    format!("[{} 01:00] wakes up", date)
+                } else {
+                    format!("[{} 00:{:02}] wakes up", date, end)
+                });
             }
+            eve = date;
         }
-        // Temporary `result` is apparently needed to make borrow checker happy.
-        let result = guards.drain()
-                           .map(|(_, v)| v)
-                           .collect::<Vec<Guard>>();
-        Ok(result)
-    }
-
-    fn read_shifts() -> Result<Vec<ShiftEntry>, Error> {
-        let mut lines =
-            BufReader::new(File::open("input/day04.txt")?)
-                      .lines()
-                      .collect::<Result<Vec<String>, _>>()?;
-        // Naive string sort is sufficient to get log entries in order.
-        lines.sort();
-
-        let mut guard_id: usize = 0;
-        lines.iter()
-             .map(|line| {
-                 match SHIFT_ENTRY_REGEX.captures(&line) {
-                     Some(caps) => {
-                         let min = caps["min"].parse::<u8>()?;
-                         match &caps["log"] {
-                             "falls asleep" => {
-                                 Ok(ShiftEntry::FallsAsleep {
-                                     guard_id: guard_id,
-                                     minute: min
-                                 })
-                             },
-                             "wakes up" => {
-                                 Ok(ShiftEntry::WakesUp {
-                                     guard_id: guard_id,
-                                     minute: min
-                                 })
-                             },
-                             _ => {
-                                 guard_id = caps["guard"].parse::<usize>()?;
-                                 Ok(ShiftEntry::ShiftStart {
-                                     guard_id: guard_id
-                                 })
-                             }
-                         }
-                     },
-                     None => Err(format_err!("Couldn't parse: {}", line))
-                 }
-             })
-             .collect::<Result<Vec<ShiftEntry>, _>>()
+        lines.reverse();
+        lines.join("\n")
+    }
+
+    #[test]
+    fn tally_matches_the_nights() {
+        prop::check("every minute asleep is counted against its guard",
+                    random_nights,
+                    prop::shrink_vec,
+                    |nights| {
+                        let log = Solver::parse_log(&log(nights)).unwrap();
+                        let guards = Solver::tally_guards(
+                            &Solver::build_shifts(&log).unwrap());
+                        guards.iter().all(|guard| {
+                            (0..60).all(|m| {
+                                let times = nights.iter().filter(|night| {
+                                    night.0 == guard.id &&
+                                    night.1.iter().any(|&(start, end)| {
+                                        start <= m && m < end
+                                    })
+                                }).count();
+                                guard.sleepy_minutes[m as usize] ==
+                                    times as u32
+                            })
+                        }) &&
+                        guards.iter().map(|g| g.nightly.len()).sum::<usize>()
+                            == nights.len()
+                    });
     }
 }
@@ -1,67 +1,350 @@
-use advent::AdventSolver;
+use advent::{AdventSolver, Context};
 use failure::Error;
+use std::collections::{BTreeSet, HashMap};
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, BufWriter, Read, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use util::evidence::Evidence;
 
 #[derive(Default)]
 pub struct Solver;
 
+// Which pairs of adjacent units react, and what's left when they do.
+//
+// Written as a comma-separated list: `case` for the puzzle's own rule (the
+// same letter in opposite cases, in any alphabet), `ab` for a and b
+// annihilating when they meet either way round, and `ab=c` for them
+// turning into c instead. A reaction can leave at most one unit, so every
+// one makes the polymer shorter and collapsing always finishes.
+pub struct Reactor {
+    opposite_case: bool,
+    pairs: HashMap<(char, char), Option<char>>,
+}
+
+impl Reactor {
+    // The puzzle's reaction.
+    pub fn polarity() -> Reactor {
+        Reactor { opposite_case: true, pairs: HashMap::new() }
+    }
+
+    // What a and b (in that order) become if they react: Some(None) for
+    // nothing at all.
+    fn react(&self, a: char, b: char) -> Option<Option<char>> {
+        match self.pairs.get(&(a, b)) {
+            Some(&product) => Some(product),
+            None if self.opposite_case && opposite_case(a, b) => Some(None),
+            None => None,
+        }
+    }
+
+    pub fn collapse(&self, polymer: &str) -> String {
+        self.run(polymer, |_| {}).iter().map(|&(_, c)| c).collect()
+    }
+
+    // Collapses the polymer, writing out every reaction as it happens and
+    // then the survivors. Tab-separated, a line per reaction and then per
+    // survivor:
+    //
+    //   reaction  step  left  right  depth  product (or -)
+    //   survivor  index  unit
+    pub fn trace<W: Write>(&self, polymer: &str, out: W)
+            -> Result<Trace, Error> {
+        let mut out = BufWriter::new(out);
+        let mut written = Ok(());
+        let (mut reactions, mut deepest) = (0, 0);
+        let survivors = self.run(polymer, |reaction| {
+            reactions += 1;
+            deepest = deepest.max(reaction.depth);
+            if written.is_ok() {
+                written = reaction.write(&mut out);
+            }
+        });
+        written?;
+        for &(i, c) in survivors.iter() {
+            writeln!(out, "survivor\t{}\t{}", i, c)?;
+        }
+        out.flush()?;
+        Ok(Trace {
+            reactions: reactions,
+            deepest: deepest,
+            survivors: survivors,
+        })
+    }
+
+    // The stack reduction behind both: what's left, with each unit's index
+    // (in chars) in the original polymer. A product is put down where the
+    // unit that set off its reaction was.
+    fn run<F: FnMut(Reaction)>(&self, polymer: &str, mut on_reaction: F)
+            -> Vec<(usize, char)> {
+        let mut stack: Vec<(usize, char)> = Vec::new();
+        let mut step = 0;
+        for (i, c) in polymer.chars().enumerate() {
+            // A product can react with what came before it in turn.
+            let mut unit = Some((i, c));
+            while let Some((i, c)) = unit.take() {
+                let top = stack.last().cloned();
+                match top.and_then(|(_, top)| self.react(top, c)) {
+                    Some(product) => {
+                        let (left, _) = stack.pop().unwrap();
+                        step += 1;
+                        on_reaction(Reaction {
+                            step: step,
+                            left: left,
+                            right: i,
+                            depth: stack.len(),
+                            product: product,
+                        });
+                        unit = product.map(|p| (i, p));
+                    },
+                    None => stack.push((i, c)),
+                }
+            }
+        }
+        stack
+    }
+}
+
+// One reaction: the indices of the two units that met (in the original
+// polymer, or where their product was put down), and how many units were
+// left on the stack beneath them afterwards.
+#[derive(Debug, PartialEq)]
+pub struct Reaction {
+    pub step: usize,
+    pub left: usize,
+    pub right: usize,
+    pub depth: usize,
+    pub product: Option<char>,
+}
+
+impl Reaction {
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let product = self.product.map_or("-".to_string(), |p| p.to_string());
+        writeln!(out, "reaction\t{}\t{}\t{}\t{}\t{}",
+                 self.step, self.left, self.right, self.depth, product)
+    }
+}
+
+// What's left of a traced polymer: how many reactions there were and the
+// deepest the stack got, with the surviving units and where each one was in
+// the original polymer.
+pub struct Trace {
+    pub reactions: usize,
+    pub deepest: usize,
+    pub survivors: Vec<(usize, char)>,
+}
+
+impl FromStr for Reactor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Reactor, Error> {
+        let mut reactor = Reactor {
+            opposite_case: false,
+            pairs: HashMap::new(),
+        };
+        for rule in s.split(',').map(|rule| rule.trim()) {
+            if rule == "case" {
+                reactor.opposite_case = true;
+                continue;
+            }
+            let (pair, product) = match rule.find('=') {
+                Some(pos) => (&rule[..pos], &rule[pos+1..]),
+                None => (rule, ""),
+            };
+            let pair: Vec<char> = pair.chars().collect();
+            let product: Vec<char> = product.chars().collect();
+            if pair.len() != 2 || product.len() > 1 {
+                return Err(format_err!(
+                    "Bad reaction rule: {:?} (expected case, ab or ab=c)",
+                    rule));
+            }
+            let product = product.first().cloned();
+            reactor.pairs.insert((pair[0], pair[1]), product);
+            reactor.pairs.insert((pair[1], pair[0]), product);
+        }
+        Ok(reactor)
+    }
+}
+
+// The puzzle's rule: aA or Aa, but not aa or AA.
+fn opposite_case(a: char, b: char) -> bool {
+    a != b && a.to_lowercase().eq(b.to_lowercase()) &&
+        (a.is_uppercase() ^ b.is_uppercase())
+}
+
 impl AdventSolver for Solver {
-    fn solve(&mut self) -> Result<(), Error> {
+    fn params(&self) -> &'static [&'static str] {
+        &["rules", "threads", "trace"]
+    }
+
+    fn solve(&mut self, ctx: &mut Context) -> Result<(), Error> {
         // Load input
         let mut polymer = String::new();
-        File::open("input/day05.txt")?.read_to_string(&mut polymer)?;
+        File::open(ctx.input_path())?.read_to_string(&mut polymer)?;
         polymer = polymer.trim().to_string();
+        let rules = ctx.param("rules", String::new())?;
+
+        let fast = rules.is_empty() && polymer.is_ascii();
+        let (collapsed_len, result) = if fast {
+            // The puzzle's own reaction, which can go a lot faster.
+            let threads = ctx.param("threads", 1usize)?;
+            let collapsed = collapse_bytes(polymer.bytes());
+            let units = Self::unit_types(&polymer);
+            let (unit, len) = best_removal(collapsed.clone(), units, threads)
+                .ok_or_else(|| format_err!("Empty polymer"))?;
+            (collapsed.len(), ((unit as char).to_string(), len))
+        } else {
+            let reactor = if rules.is_empty() {
+                Reactor::polarity()
+            } else {
+                rules.parse::<Reactor>()?
+            };
+            Self::solve_with(&reactor, &polymer)?
+        };
+
+        let trace_path = ctx.param("trace", String::new())?;
+        if !trace_path.is_empty() {
+            let reactor = if rules.is_empty() {
+                Reactor::polarity()
+            } else {
+                rules.parse::<Reactor>()?
+            };
+            let trace = reactor.trace(&polymer, File::create(&trace_path)?)?;
+            if trace.survivors.len() != collapsed_len {
+                return Err(format_err!(
+                    "Trace left {} units, but the polymer collapsed to {}",
+                    trace.survivors.len(), collapsed_len));
+            }
+            println!("Trace of {} reactions (deepest stack {}, {} survivors) \
+                      saved to {}.", trace.reactions, trace.deepest,
+                     trace.survivors.len(), trace_path);
+        }
 
         // Part 1, collapse the input polymer
-        let collapsed = Self::collapse_polymer(&polymer);
-        println!("Collapsed length: {}", collapsed.len());
+        println!("Collapsed length: {}", collapsed_len);
+        ctx.answer(1, collapsed_len);
 
         // Part 2, try collapsing with a unit removed
-        let result = "abcdefghijklmnopqrstuvwxyz"
-            .chars()
-            .map(|unit| {
-                let polymer = Solver::remove_unit(&polymer, unit);
-                let collapsed = Self::collapse_polymer(&polymer);
-                (unit, collapsed.len())
-            })
-            .min_by_key(|&(_unit, len)| len)
-            .unwrap();
         println!("Without {}, collapsed length: {}", result.0, result.1);
+        ctx.answer(2, result.1);
+
+        if ctx.explaining() {
+            let rules = if rules.is_empty() { "case" } else { &rules };
+            ctx.explain("part1", Evidence::record()
+                .with("rules", rules)
+                .with("polymer_length", polymer.chars().count())
+                .with("collapsed_length", collapsed_len));
+            ctx.explain("part2", Evidence::record()
+                .with("removed_unit", result.0.clone())
+                .with("collapsed_length", result.1));
+        }
         Ok(())
     }
 }
 
-impl Solver {
-    fn collapse_polymer(polymer: &str) -> String {
-        let mut stack = Vec::new();
-        for c in polymer.chars() {
-            stack.push(c);
-            while stack.len() > 1 &&
-                  Self::should_annihilate(stack[stack.len()-2],
-                                          stack[stack.len()-1]) {
-                stack.pop();
+// The puzzle's reaction on ASCII, as bytes: a letter and the same one in the
+// other case differ by only the 0x20 bit.
+fn collapse_bytes<I: Iterator<Item=u8>>(polymer: I) -> Vec<u8> {
+    let mut stack = Vec::new();
+    for unit in polymer {
+        match stack.last() {
+            Some(&top) if top ^ unit == 0x20 &&
+                          unit.is_ascii_alphabetic() => {
                 stack.pop();
-            }
+            },
+            _ => stack.push(unit),
         }
-        stack.iter().collect()
     }
+    stack
+}
 
-    // Return the polymer minus any occurrences of a given unit.
-    fn remove_unit(polymer: &str, unit: char) -> String {
-        polymer.chars()
-               .filter(|c| !c.eq_ignore_ascii_case(&unit))
+// The unit type (lowercase) whose removal collapses shortest, and how short.
+// Any pair that reacts in the whole polymer still reacts with another unit
+// type taken out, so it's enough to start from the collapsed polymer, which
+// is usually much shorter. With more than one thread, the unit types are
+// shared out between them.
+fn best_removal(collapsed: Vec<u8>, units: Vec<u8>, threads: usize)
+        -> Option<(u8, usize)> {
+    let without = |collapsed: &[u8], unit: u8| {
+        collapse_bytes(collapsed.iter()
+                                .cloned()
+                                .filter(|b| b.to_ascii_lowercase() != unit))
+            .len()
+    };
+    let mut results: Vec<(u8, usize)> = if threads <= 1 {
+        units.iter().map(|&unit| (unit, without(&collapsed, unit))).collect()
+    } else {
+        let collapsed = Arc::new(collapsed);
+        let share = units.len().div_ceil(threads);
+        let handles = units.chunks(share.max(1))
+                           .map(|chunk| {
+                               let chunk = chunk.to_vec();
+                               let collapsed = collapsed.clone();
+                               thread::spawn(move || {
+                                   chunk.iter()
+                                        .map(|&u| (u, without(&collapsed, u)))
+                                        .collect::<Vec<(u8, usize)>>()
+                               })
+                           })
+                           .collect::<Vec<_>>();
+        handles.into_iter()
+               .flat_map(|handle| handle.join().unwrap())
+               .collect()
+    };
+    results.sort();
+    results.into_iter().min_by_key(|&(_unit, len)| len)
+}
+
+impl Solver {
+    // Both parts with any rules: the collapsed length, and the unit type to
+    // take out for the shortest collapse.
+    fn solve_with(reactor: &Reactor, polymer: &str)
+            -> Result<(usize, (String, usize)), Error> {
+        let collapsed = reactor.collapse(polymer).chars().count();
+        let units: BTreeSet<String> = polymer.chars()
+                                             .map(|c| c.to_lowercase()
+                                                       .collect())
+                                             .collect();
+        let result = units
+            .into_iter()
+            .map(|unit| {
+                let polymer = Solver::remove_unit(polymer, &unit);
+                let collapsed = reactor.collapse(&polymer);
+                (unit, collapsed.chars().count())
+            })
+            .min_by_key(|&(_, len)| len)
+            .ok_or_else(|| format_err!("Empty polymer"))?;
+        Ok((collapsed, result))
+    }
+
+    // The different units in an ASCII polymer, in lowercase, sorted.
+    fn unit_types(polymer: &str) -> Vec<u8> {
+        polymer.bytes()
+               .map(|b| b.to_ascii_lowercase())
+               .collect::<BTreeSet<u8>>()
+               .into_iter()
                .collect()
     }
 
-    fn should_annihilate(c1: char, c2: char) -> bool {
-        c1.eq_ignore_ascii_case(&c2) && (c1.is_uppercase() ^ c2.is_uppercase())
+    #[cfg(test)]
+    fn collapse_polymer(polymer: &str) -> String {
+        Reactor::polarity().collapse(polymer)
+    }
+
+    // Return the polymer minus any occurrences of a given unit, in either
+    // case (`unit` is its lowercase form).
+    fn remove_unit(polymer: &str, unit: &str) -> String {
+        polymer.chars()
+               .filter(|c| !c.to_lowercase().eq(unit.chars()))
+               .collect()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Solver;
+    use super::{best_removal, collapse_bytes, opposite_case, Reactor, Solver};
+    use util::prop;
     static EXAMPLE_POLYMER: &str = "dabAcCaCBAcCcaDA";
 
     #[test]
@@ -98,4 +381,109 @@ mod tests {
     fn collapse_polymer_nothing_to_do() {
         assert_eq!("AbCdEfG", Solver::collapse_polymer("AbCdEfG"));
     }
+
+    #[test]
+    fn collapse_polymer_is_idempotent() {
+        prop::check("collapsing twice changes nothing",
+                    |rng, size| prop::string_from(rng, size, "aAbBcC"),
+                    prop::shrink_string,
+                    |polymer| {
+                        let once = Solver::collapse_polymer(polymer);
+                        Solver::collapse_polymer(&once) == once
+                    });
+    }
+
+    #[test]
+    fn collapse_polymer_leaves_no_reacting_pairs() {
+        prop::check("no adjacent units react after collapsing",
+                    |rng, size| prop::string_from(rng, size, "aAbBcC"),
+                    prop::shrink_string,
+                    |polymer| {
+                        let units: Vec<char> =
+                            Solver::collapse_polymer(polymer).chars().collect();
+                        units.windows(2).all(|pair| {
+                            !opposite_case(pair[0], pair[1])
+                        })
+                    });
+    }
+
+    #[test]
+    fn rules_preset_matches_the_puzzle() {
+        let reactor = "case".parse::<Reactor>().unwrap();
+        assert_eq!("dabCBAcaDA", reactor.collapse(EXAMPLE_POLYMER));
+        assert_eq!("dbCBcD", reactor.collapse(
+            &Solver::remove_unit(EXAMPLE_POLYMER, "a")));
+    }
+
+    #[test]
+    fn custom_rules() {
+        // Pairs react either way round, and only the listed ones.
+        let reactor = "xy, +-".parse::<Reactor>().unwrap();
+        assert_eq!("aAz", reactor.collapse("aAxyzyx+-"));
+        assert_eq!("", reactor.collapse("x+-y"));
+        // A product can go on to react with what's before it.
+        let reactor = "ab=c, cd".parse::<Reactor>().unwrap();
+        assert_eq!("e", reactor.collapse("dabe"));
+        assert_eq!("c", reactor.collapse("ba"));
+        assert!("abc".parse::<Reactor>().is_err());
+        assert!("ab=cd".parse::<Reactor>().is_err());
+    }
+
+    #[test]
+    fn non_ascii_units() {
+        let reactor = Reactor::polarity();
+        assert_eq!("", reactor.collapse("ΩωäÄ"));
+        assert_eq!("ωω", reactor.collapse("ωω"));
+        let reactor = "→←, ☃☂=❄".parse::<Reactor>().unwrap();
+        assert_eq!("❄", reactor.collapse("☂→←☃"));
+        assert_eq!("Ωω", Solver::remove_unit("äΩÄωÄ", "ä"));
+    }
+
+    #[test]
+    fn byte_reducer_matches_reactor() {
+        prop::check("byte reducer matches the general one",
+                    |rng, size| prop::string_from(rng, size, "aAbBcCdD1"),
+                    prop::shrink_string,
+                    |polymer| {
+                        let reactor = Reactor::polarity();
+                        let bytes = collapse_bytes(polymer.bytes());
+                        if bytes != reactor.collapse(polymer).as_bytes() {
+                            return false;
+                        }
+                        let expected = Solver::solve_with(&reactor, polymer)
+                            .ok()
+                            .map(|(_, (unit, len))| (unit.as_bytes()[0], len));
+                        let units = Solver::unit_types(polymer);
+                        (1..4).all(|threads| {
+                            best_removal(bytes.clone(), units.clone(),
+                                         threads) == expected
+                        })
+                    });
+    }
+
+    #[test]
+    fn trace() {
+        let mut out = Vec::new();
+        let trace = Reactor::polarity().trace("abBAcdDeC", &mut out).unwrap();
+        assert_eq!((3, 1), (trace.reactions, trace.deepest));
+        assert_eq!(vec![(4, 'c'), (7, 'e'), (8, 'C')], trace.survivors);
+        assert_eq!("reaction\t1\t1\t2\t1\t-\n\
+                    reaction\t2\t0\t3\t0\t-\n\
+                    reaction\t3\t5\t6\t1\t-\n\
+                    survivor\t4\tc\nsurvivor\t7\te\nsurvivor\t8\tC\n",
+                   String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn trace_products() {
+        // ab=c at 1 leaves c at 2, which then reacts with d at 0.
+        let reactor = "ab=c, cd".parse::<Reactor>().unwrap();
+        let mut out = Vec::new();
+        let trace = reactor.trace("dabe", &mut out).unwrap();
+        assert_eq!("reaction\t1\t1\t2\t1\tc\n\
+                    reaction\t2\t0\t2\t0\t-\n\
+                    survivor\t3\te\n",
+                   String::from_utf8(out).unwrap());
+        assert_eq!(vec![(3, 'e')], trace.survivors);
+    }
 }
@@ -1,140 +1,871 @@
-use advent::AdventSolver;
+use advent::{AdventSolver, Context};
 use failure::Error;
 use regex::Regex;
-use std::collections::HashSet;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use util::evidence::Evidence;
 
 #[derive(Default)]
 pub struct Solver;
 
-// I don't like this one. Not cleaning it up. 🤯
+// How far apart two points are.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Metric {
+    Manhattan,
+    Chebyshev,
+    // Squared, to stay exact in integers. Nearest is nearest either way.
+    SquaredEuclidean,
+    // Manhattan, with a step along x or y costing this much.
+    WeightedManhattan(u64, u64),
+}
 
-impl AdventSolver for Solver {
-    fn solve(&mut self) -> Result<(), Error> {
-        let coords = Self::read_coordinates()?;
-        let grid = Self::make_grid(&coords);
-        let mut areas = coords.iter()
-                              .map(|_| 0)
-                              .collect::<Vec<usize>>();
-        let mut finite_areas: HashSet<usize> =
-            (0..coords.len()).collect();
-
-        for x in 0..grid.len() {
-            for y in 0..grid[x].len() {
-                match grid[x][y] {
-                    Some(index) => {
-                        areas[index] += 1;
-                        // My theory: Any area that reaches the bounding box
-                        // will go on forever.
-                        if x == 0 || y == 0 || x == grid.len()-1
-                                            || y == grid[0].len()-1 {
-                            finite_areas.remove(&index);
-                        }
+const KING_MOVES: [(i32, i32); 8] =
+    [(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (-1, 1), (1, -1), (1, 1)];
+
+impl Metric {
+    fn distance(&self, p1: (i32, i32), p2: (i32, i32)) -> u64 {
+        let dx = (p1.0 as i64 - p2.0 as i64).unsigned_abs();
+        let dy = (p1.1 as i64 - p2.1 as i64).unsigned_abs();
+        match *self {
+            Metric::Manhattan => dx + dy,
+            Metric::Chebyshev => dx.max(dy),
+            Metric::SquaredEuclidean => dx * dx + dy * dy,
+            Metric::WeightedManhattan(wx, wy) => wx * dx + wy * dy,
+        }
+    }
+
+    // The steps a flood fill can take, if it works for this metric. It
+    // needs every cell to have a neighbour one step towards each of its
+    // nearest coordinates, with that coordinate still among the nearest.
+    // A step towards it along an axis (or diagonally, for Chebyshev) gets
+    // that much closer to it, and no closer than that to anything else, so
+    // these three are fine. Squared Euclidean isn't so simple.
+    fn flood_steps(&self) -> Option<&'static [(i32, i32)]> {
+        match *self {
+            Metric::Manhattan | Metric::WeightedManhattan(_, _) => {
+                Some(&KING_MOVES[..4])
+            },
+            Metric::Chebyshev => Some(&KING_MOVES),
+            Metric::SquaredEuclidean => None,
+        }
+    }
+
+    // Which regions go on forever.
+    //
+    // For the Manhattan metrics, a step away from the box adds the same to
+    // the distance to every coordinate, so a cell outside the box has the
+    // same owner as the nearest one on its edge. For Chebyshev, the same
+    // goes once a cell is further out than the box is wide or high: then
+    // either one axis is the furthest for every coordinate, or the cell is
+    // off a corner and a diagonal step adds one to both. So in both cases,
+    // a region is infinite exactly when it has a cell on the ring that far
+    // out. For squared Euclidean, regions are convex polygons, and they're
+    // unbounded just for the coordinates on the edge of the convex hull (if
+    // nothing else is in the same place).
+    fn infinite_regions(&self, coords: &[(i32, i32)], bounds: (i32, i32, i32,
+                        i32)) -> Vec<bool> {
+        let (min_x, min_y, max_x, max_y) = bounds;
+        let margin = match *self {
+            Metric::SquaredEuclidean => return on_hull(coords),
+            _ => self.finite_margin(bounds),
+        };
+        let (min_x, min_y) = (min_x - margin, min_y - margin);
+        let (max_x, max_y) = (max_x + margin, max_y + margin);
+        let ring = (min_x..=max_x)
+            .flat_map(|x| vec![(x, min_y), (x, max_y)])
+            .chain((min_y..=max_y).flat_map(|y| vec![(min_x, y), (max_x, y)]));
+        let mut infinite = vec![false; coords.len()];
+        for point in ring {
+            if let Some(i) = self.nearest(point, coords) {
+                infinite[i] = true;
+            }
+        }
+        infinite
+    }
+
+    // How far out from the box the ring in infinite_regions is. Finite
+    // regions stay inside it. Not for squared Euclidean, where finite
+    // regions can reach much further (see euclidean_rows).
+    fn finite_margin(&self, bounds: (i32, i32, i32, i32)) -> i32 {
+        let (min_x, min_y, max_x, max_y) = bounds;
+        match *self {
+            Metric::Chebyshev => (max_x - min_x).max(max_y - min_y) + 1,
+            _ => 0,
+        }
+    }
+
+    // The one coordinate nearest the point, if there's just one.
+    fn nearest(&self, point: (i32, i32), coords: &[(i32, i32)])
+            -> Option<usize> {
+        let mut min_distance = u64::max_value();
+        let mut nearest = None;
+        for (index, &coord) in coords.iter().enumerate() {
+            let distance = self.distance(point, coord);
+            if distance < min_distance {
+                min_distance = distance;
+                nearest = Some(index);
+            } else if distance == min_distance {
+                nearest = None;
+            }
+        }
+        nearest
+    }
+}
+
+impl FromStr for Metric {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Metric, Error> {
+        match s {
+            "manhattan" => Ok(Metric::Manhattan),
+            "chebyshev" => Ok(Metric::Chebyshev),
+            "euclidean" => Ok(Metric::SquaredEuclidean),
+            _ if s.starts_with("weighted:") => {
+                let weights = s["weighted:".len()..]
+                    .split(',')
+                    .map(|w| w.trim().parse::<u64>())
+                    .collect::<Result<Vec<u64>, _>>()?;
+                match weights[..] {
+                    [wx, wy] if wx > 0 && wy > 0 => {
+                        Ok(Metric::WeightedManhattan(wx, wy))
                     },
-                    None => {}
+                    _ => Err(format_err!("Expected weighted:X,Y with both \
+                                          weights above 0, got {}", s)),
                 }
+            },
+            _ => Err(format_err!("Unknown metric: {} (expected manhattan, \
+                                  chebyshev, euclidean or weighted:X,Y)", s)),
+        }
+    }
+}
+
+// Which points are on the edge of their convex hull (corners or not), and
+// nowhere that another point is too.
+fn on_hull(points: &[(i32, i32)]) -> Vec<bool> {
+    let cross = |o: (i32, i32), a: (i32, i32), b: (i32, i32)| {
+        (a.0 as i64 - o.0 as i64) * (b.1 as i64 - o.1 as i64) -
+        (a.1 as i64 - o.1 as i64) * (b.0 as i64 - o.0 as i64)
+    };
+    // Monotone chain, corners only.
+    let mut sorted = points.to_vec();
+    sorted.sort();
+    sorted.dedup();
+    let mut hull: Vec<(i32, i32)> = Vec::new();
+    for pass in 0..2 {
+        let start = hull.len();
+        for &p in sorted.iter() {
+            while hull.len() >= start + 2 &&
+                  cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0 {
+                hull.pop();
             }
+            hull.push(p);
+        }
+        hull.pop();
+        if pass == 0 {
+            sorted.reverse();
+        }
+    }
+    let on_edge = |p: (i32, i32)| {
+        if hull.len() < 3 {
+            // All in a line.
+            return true;
         }
+        (0..hull.len()).any(|i| {
+            let (a, b) = (hull[i], hull[(i + 1) % hull.len()]);
+            cross(a, b, p) == 0 &&
+                p.0 >= a.0.min(b.0) && p.0 <= a.0.max(b.0) &&
+                p.1 >= a.1.min(b.1) && p.1 <= a.1.max(b.1)
+        })
+    };
+    points.iter()
+          .map(|&p| {
+              points.iter().filter(|&&q| q == p).count() == 1 && on_edge(p)
+          })
+          .collect()
+}
 
-        let largest_finite_area =
-            areas.iter()
-                 .enumerate()
-                 .filter(|&(index, _area)| finite_areas.contains(&index))
-                 .max_by_key(|&(_index, area)| area)
-                 .unwrap();
-        println!("Largest finite area: coords[{}] {:?}: {}",
-                 largest_finite_area.0, coords[largest_finite_area.0],
-                 largest_finite_area.1);
-
-        // Part 2: Count positions with < 10000 total distance to coords
-        let mut found_something = true;
-        let mut min_x: i32 = (grid.len()/2) as i32;
-        let mut max_x: i32 = (grid.len()/2) as i32;
-        let mut min_y: i32 = (grid[0].len()/2) as i32;
-        let mut max_y: i32 = (grid[0].len()/2) as i32;
-        let mut region_size: usize = 0;
-        while found_something {
-            found_something = false;
-            for x in min_x..=max_x {
-                let range = if x == min_x || x == max_x {
-                    (min_y..=max_y).collect()
-                } else {
-                    vec![min_y, max_y]
+// The cells of a finite squared Euclidean region, row by row, as (y, first
+// x, last x). Coordinates near the edge of the hull can have regions that
+// reach a long way out of the box, so they're worked out directly.
+fn euclidean_rows(coords: &[(i32, i32)], i: usize) -> Vec<(i64, i64, i64)> {
+    let mut rows = Vec::new();
+    // The region is convex, and its own coordinate's row is in it, so the
+    // rows with any of it (between cells or not) are the ones either side
+    // of that until there's a row without.
+    for &(start, step) in [(0, -1), (1, 1)].iter() {
+        let mut y = coords[i].1 as i64 + start;
+        while let Some((first, last)) = euclidean_row(coords, i, y) {
+            if first <= last {
+                rows.push((y, first, last));
+            }
+            y += step;
+        }
+    }
+    rows.sort();
+    rows
+}
+
+// The first and last x in row y nearer coordinate i than any other, or None
+// if no point in the row is, whole number or not. Nearer i than each other
+// coordinate is a half-plane, so that's x above or below some fraction.
+fn euclidean_row(coords: &[(i32, i32)], i: usize, y: i64)
+        -> Option<(i64, i64)> {
+    let (px, py) = (coords[i].0 as i128, coords[i].1 as i128);
+    let y = y as i128;
+    // As fractions, with positive denominators.
+    let mut lower: Option<(i128, i128)> = None;
+    let mut upper: Option<(i128, i128)> = None;
+    for (j, &(sx, sy)) in coords.iter().enumerate() {
+        if j == i {
+            continue;
+        }
+        let (sx, sy) = (sx as i128, sy as i128);
+        // Nearer (px, py) than (sx, sy) when a * x < c.
+        let a = 2 * (sx - px);
+        let c = sx * sx + sy * sy - px * px - py * py - 2 * y * (sy - py);
+        if a == 0 {
+            if c <= 0 {
+                return None;
+            }
+        } else if a > 0 {
+            if upper.is_none_or(|(n, d)| c * d < n * a) {
+                upper = Some((c, a));
+            }
+        } else if lower.is_none_or(|(n, d)| -c * d > n * -a) {
+            lower = Some((-c, -a));
+        }
+    }
+    // A finite region is bounded on both sides in every row it's in.
+    let ((ln, ld), (un, ud)) = (lower?, upper?);
+    if ln * ud >= un * ld {
+        return None;
+    }
+    Some(((ln.div_euclid(ld) + 1) as i64,
+          (-(-un).div_euclid(ud) - 1) as i64))
+}
+
+// What a distance along one axis costs.
+#[derive(Clone, Copy)]
+enum AxisCost {
+    Linear(u64),
+    Square,
+}
+
+// The total cost from all the values on an axis to any position along it.
+// With sorted prefix sums, each total takes a binary search rather than going
+// through them all. The total is convex, lowest at `lowest()`.
+struct AxisTotals {
+    cost: AxisCost,
+    sorted: Vec<i128>,
+    prefix: Vec<i128>,
+    squares: i128,
+}
+
+impl AxisTotals {
+    fn new(values: &[i64], cost: AxisCost) -> AxisTotals {
+        let mut sorted: Vec<i128> =
+            values.iter().map(|&v| v as i128).collect();
+        sorted.sort();
+        let mut prefix = vec![0i128];
+        for &v in sorted.iter() {
+            let last = *prefix.last().unwrap();
+            prefix.push(last + v);
+        }
+        let squares = sorted.iter().map(|&v| v * v).sum::<i128>();
+        AxisTotals {
+            cost: cost,
+            sorted: sorted,
+            prefix: prefix,
+            squares: squares,
+        }
+    }
+
+    fn total(&self, p: i128) -> i128 {
+        let n = self.sorted.len();
+        match self.cost {
+            AxisCost::Linear(w) => {
+                let below = match self.sorted.binary_search_by(|&v| {
+                    if v <= p { Ordering::Less } else { Ordering::Greater }
+                }) {
+                    Ok(k) | Err(k) => k,
                 };
-                for y in range {
-                    let sum = coords.iter()
-                                    .map(|&coord| {
-                                        Self::manhattan_distance((x, y), coord)
-                                    })
-                                    .sum::<u32>();
-                    if sum < 10000 {
-                        found_something = true;
-                        region_size += 1;
+                let left = p * below as i128 - self.prefix[below];
+                let right = (self.prefix[n] - self.prefix[below]) -
+                            p * (n - below) as i128;
+                w as i128 * (left + right)
+            },
+            AxisCost::Square => {
+                n as i128 * p * p - 2 * p * self.prefix[n] + self.squares
+            },
+        }
+    }
+
+    // Where the total is lowest: the median, or the nearest whole number to
+    // the mean.
+    fn lowest(&self) -> i128 {
+        let n = self.sorted.len();
+        match self.cost {
+            AxisCost::Linear(_) => self.sorted[n / 2],
+            AxisCost::Square => {
+                let mean = self.prefix[n].div_euclid(n as i128);
+                if self.total(mean + 1) < self.total(mean) { mean + 1 }
+                else { mean }
+            },
+        }
+    }
+
+    // The first and last positions with a total under the budget, if any.
+    fn run_under(&self, budget: i128) -> Option<(i128, i128)> {
+        let lowest = self.lowest();
+        if self.total(lowest) >= budget {
+            return None;
+        }
+        Some((self.last_under(lowest, -1, budget),
+              self.last_under(lowest, 1, budget)))
+    }
+
+    // Stepping away from `from` (which is under the budget), the last
+    // position that's still under: doubling the distance until it's over,
+    // then a binary search back.
+    fn last_under(&self, from: i128, step: i128, budget: i128) -> i128 {
+        let under = |d: i128| self.total(from + step * d) < budget;
+        let (mut good, mut bad) = (0, 1);
+        while under(bad) {
+            good = bad;
+            bad *= 2;
+        }
+        while bad - good > 1 {
+            let mid = (good + bad) / 2;
+            if under(mid) { good = mid } else { bad = mid }
+        }
+        from + step * good
+    }
+}
+
+// Who's closest to a cell.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Owner {
+    Coord(usize),
+    // Two or more coordinates are equally close.
+    Tied,
+}
+
+// The coordinates' bounding box, with each cell labelled by its nearest
+// coordinate, and the size of every coordinate's region.
+struct Voronoi {
+    min_x: i32,
+    min_y: i32,
+    width: usize,
+    height: usize,
+    owners: Vec<Owner>,
+    // Region sizes, whole for finite regions and within the box for
+    // infinite ones, and whether they go on forever.
+    areas: Vec<usize>,
+    infinite: Vec<bool>,
+    tied: usize,
+    // How far out of the box finite regions can reach, except for squared
+    // Euclidean ones.
+    margin: i32,
+}
+
+impl Voronoi {
+    fn new(coords: &[(i32, i32)], metric: Metric) -> Result<Voronoi, Error> {
+        if coords.is_empty() {
+            return Err(format_err!("No coordinates"));
+        }
+        let min_x = coords.iter().map(|c| c.0).min().unwrap();
+        let max_x = coords.iter().map(|c| c.0).max().unwrap();
+        let min_y = coords.iter().map(|c| c.1).min().unwrap();
+        let max_y = coords.iter().map(|c| c.1).max().unwrap();
+        let width = (max_x - min_x + 1) as usize;
+        let height = (max_y - min_y + 1) as usize;
+        let nearest = match metric.flood_steps() {
+            Some(steps) => {
+                Self::flood(coords, metric, steps, (min_x, min_y),
+                            (width, height))
+            },
+            None => Self::scan(coords, metric, (min_x, min_y),
+                               (width, height)),
+        };
+        let owners: Vec<Owner> = nearest.iter()
+                                        .map(|nearest| match nearest[..] {
+                                            [i] => Owner::Coord(i),
+                                            _ => Owner::Tied,
+                                        })
+                                        .collect();
+        let bounds = (min_x, min_y, max_x, max_y);
+        let infinite = metric.infinite_regions(coords, bounds);
+
+        let mut areas = vec![0; coords.len()];
+        let mut tied = 0;
+        for owner in owners.iter() {
+            match *owner {
+                Owner::Coord(i) => areas[i] += 1,
+                Owner::Tied => tied += 1,
+            }
+        }
+        // The rest of the finite regions, outside the box.
+        let margin = metric.finite_margin(bounds);
+        for x in min_x - margin..=max_x + margin {
+            for y in min_y - margin..=max_y + margin {
+                if x >= min_x && x <= max_x && y >= min_y && y <= max_y {
+                    continue;
+                }
+                match metric.nearest((x, y), coords) {
+                    Some(i) if !infinite[i] => areas[i] += 1,
+                    _ => (),
+                }
+            }
+        }
+        if metric == Metric::SquaredEuclidean {
+            for i in (0..coords.len()).filter(|&i| !infinite[i]) {
+                areas[i] = euclidean_rows(coords, i)
+                    .iter()
+                    .map(|&(_, first, last)| (last - first + 1) as usize)
+                    .sum();
+            }
+        }
+        Ok(Voronoi {
+            min_x: min_x,
+            min_y: min_y,
+            width: width,
+            height: height,
+            owners: owners,
+            areas: areas,
+            infinite: infinite,
+            tied: tied,
+            margin: margin,
+        })
+    }
+
+    // Floods out from every coordinate at once, passing each cell's
+    // nearest coordinates on to its neighbours, which keep whichever are
+    // nearest to them. Anything that changes gets passed on again, until
+    // nothing does. Each cell's nearest coordinates come to it along a path
+    // of cells they're nearest to (see Metric::flood_steps), so it finds
+    // all of them.
+    fn flood(coords: &[(i32, i32)], metric: Metric, steps: &[(i32, i32)],
+             (min_x, min_y): (i32, i32), (width, height): (usize, usize))
+            -> Vec<Vec<usize>> {
+        let mut nearest: Vec<Vec<usize>> = vec![Vec::new(); width * height];
+        let mut distance = vec![u64::max_value(); width * height];
+        let mut queue = VecDeque::new();
+        for (i, &(x, y)) in coords.iter().enumerate() {
+            let cell = (y - min_y) as usize * width + (x - min_x) as usize;
+            // Two coordinates in the same place tie from the start.
+            nearest[cell].push(i);
+            distance[cell] = 0;
+            queue.push_back(cell);
+        }
+        while let Some(cell) = queue.pop_front() {
+            let (x, y) = ((cell % width) as i32, (cell / width) as i32);
+            for &(dx, dy) in steps {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= width as i32 ||
+                   ny >= height as i32 {
+                    continue;
+                }
+                let next = ny as usize * width + nx as usize;
+                let point = (min_x + nx, min_y + ny);
+                let mut changed = false;
+                for k in 0..nearest[cell].len() {
+                    let i = nearest[cell][k];
+                    let d = metric.distance(point, coords[i]);
+                    if d < distance[next] {
+                        distance[next] = d;
+                        nearest[next].clear();
+                        nearest[next].push(i);
+                        changed = true;
+                    } else if d == distance[next] &&
+                              !nearest[next].contains(&i) {
+                        nearest[next].push(i);
+                        changed = true;
                     }
                 }
+                if changed {
+                    queue.push_back(next);
+                }
             }
-            min_x -= 1;
-            max_x += 1;
-            min_y -= 1;
-            max_y += 1;
-            eprint!("\r{} {} {}", region_size, min_x, min_y);
         }
-        eprint!("\r");
-        println!("Area with locations with total distance < 10k: {}",
-                 region_size);
-        Ok(())
+        nearest
     }
-}
 
-impl Solver {
-    // Grid returned as a two-dim row ordered Vec<Vec>> where each item is
-    // either Some(index of closest point in coords) or None (if there is no
-    // unique closest point). Grid's (0, 0) is (min_x, min_y).
-    fn make_grid(coords: &Vec<(i32, i32)>) -> Vec<Vec<Option<usize>>> {
-        let min_x = coords.iter().min_by_key(|c| c.0).unwrap().0;
-        let max_x = coords.iter().max_by_key(|c| c.0).unwrap().0;
-        let min_y = coords.iter().min_by_key(|c| c.1).unwrap().1;
-        let max_y = coords.iter().max_by_key(|c| c.1).unwrap().1;
-        let mut result: Vec<Vec<Option<usize>>> = Vec::new();
-        for x in min_x..=max_x {
-            let mut row = Vec::new();
-            for y in min_y..=max_y {
-                row.push(Self::nearest_point((x, y), coords));
+    // Every cell against the coordinates, going out from its column in
+    // order of x and stopping once the x distance alone is too far.
+    fn scan(coords: &[(i32, i32)], metric: Metric,
+            (min_x, min_y): (i32, i32), (width, height): (usize, usize))
+            -> Vec<Vec<usize>> {
+        let mut by_x: Vec<usize> = (0..coords.len()).collect();
+        by_x.sort_by_key(|&i| coords[i].0);
+        let mut nearest = Vec::with_capacity(width * height);
+        for y in min_y..min_y + height as i32 {
+            for x in min_x..min_x + width as i32 {
+                let start = by_x.iter()
+                                .position(|&i| coords[i].0 >= x)
+                                .unwrap_or(by_x.len());
+                let mut best = u64::max_value();
+                let mut cell: Vec<usize> = Vec::new();
+                let right = by_x[start..].iter();
+                let left = by_x[..start].iter().rev();
+                for side in [Box::new(right) as Box<dyn Iterator<Item=_>>,
+                             Box::new(left)] {
+                    for &i in side {
+                        let floor = metric.distance((x, y), (coords[i].0, y));
+                        if floor > best {
+                            break;
+                        }
+                        let d = metric.distance((x, y), coords[i]);
+                        if d < best {
+                            best = d;
+                            cell.clear();
+                        }
+                        if d == best {
+                            cell.push(i);
+                        }
+                    }
+                }
+                cell.sort();
+                nearest.push(cell);
             }
-            result.push(row);
         }
-        result
+        nearest
+    }
+
+    fn owner(&self, x: i32, y: i32) -> Owner {
+        let (x, y) = ((x - self.min_x) as usize, (y - self.min_y) as usize);
+        self.owners[y * self.width + x]
+    }
+
+    // The owner of any cell, in the box or not.
+    fn owner_anywhere(&self, point: (i32, i32), coords: &[(i32, i32)],
+                      metric: Metric) -> Owner {
+        let (x, y) = point;
+        if x >= self.min_x && y >= self.min_y &&
+           x < self.min_x + self.width as i32 &&
+           y < self.min_y + self.height as i32 {
+            return self.owner(x, y);
+        }
+        match metric.nearest(point, coords) {
+            Some(i) => Owner::Coord(i),
+            None => Owner::Tied,
+        }
     }
 
-    fn nearest_point(point: (i32, i32),
-                     coords: &Vec<(i32, i32)>) -> Option<usize> {
-        let mut min_distance: Option<u32> = None;
-        let mut nearest_indexes: Vec<usize> = Vec::new();
-        for (index, coord) in coords.iter().enumerate() {
-            let dist = Self::manhattan_distance(point, *coord);
-            if min_distance.is_none() || dist < min_distance.unwrap() {
-                min_distance = Some(dist);
-                nearest_indexes.clear();
-                nearest_indexes.push(index);
-            } else if dist == min_distance.unwrap() {
-                nearest_indexes.push(index);
+    // Every cell of a finite region, in order.
+    fn cells(&self, i: usize, coords: &[(i32, i32)], metric: Metric)
+            -> Vec<(i32, i32)> {
+        let mut cells = Vec::new();
+        if metric == Metric::SquaredEuclidean {
+            for (y, first, last) in euclidean_rows(coords, i) {
+                cells.extend((first..=last).map(|x| (x as i32, y as i32)));
+            }
+            cells.sort();
+            return cells;
+        }
+        let (min_x, min_y) = (self.min_x - self.margin,
+                              self.min_y - self.margin);
+        for x in min_x..min_x + self.width as i32 + 2 * self.margin {
+            for y in min_y..min_y + self.height as i32 + 2 * self.margin {
+                if self.owner_anywhere((x, y), coords, metric) ==
+                   Owner::Coord(i) {
+                    cells.push((x, y));
+                }
             }
         }
-        if nearest_indexes.len() > 1 {
-            None
-        } else {
-            Some(nearest_indexes[0])
+        cells
+    }
+
+    // The coordinates with the largest finite region (more than one if
+    // they tie), and its size.
+    fn largest_finite(&self) -> Option<(Vec<usize>, usize)> {
+        let largest = (0..self.areas.len())
+            .filter(|&i| !self.infinite[i])
+            .map(|i| self.areas[i])
+            .max()?;
+        Some(((0..self.areas.len())
+                  .filter(|&i| !self.infinite[i] && self.areas[i] == largest)
+                  .collect(),
+              largest))
+    }
+}
+
+const TIED: [u8; 3] = [0x60, 0x60, 0x60];
+const WINNER: [u8; 3] = [0xff, 0xd7, 0x00];
+const COORDINATE: [u8; 3] = [0x00, 0x00, 0x00];
+const SAFE_EDGE: [u8; 3] = [0xff, 0xff, 0xff];
+// Small enough to fit in memory.
+const MAX_PIXELS: usize = 1 << 24;
+
+// A colour for each region, going round the colour wheel by the golden
+// angle so that neighbouring indexes don't look alike. Infinite regions are
+// darker.
+fn region_color(index: usize, infinite: bool) -> [u8; 3] {
+    let hue = (index as f64 * 0.618_033_988_75).fract() * 6.0;
+    let value = if infinite { 0.35 } else { 0.8 };
+    let chroma = value * 0.55;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as usize {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = value - chroma;
+    [((r + m) * 255.0) as u8, ((g + m) * 255.0) as u8,
+     ((b + m) * 255.0) as u8]
+}
+
+// An RGB picture of the regions, `scale` pixels to a cell.
+struct Picture {
+    left: i32,
+    top: i32,
+    // In cells.
+    columns: usize,
+    rows: usize,
+    scale: usize,
+    buf: Vec<u8>,
+}
+
+impl Picture {
+    fn new(left: i32, top: i32, columns: usize, rows: usize, scale: usize)
+            -> Result<Picture, Error> {
+        let pixels = columns.saturating_mul(rows)
+                            .saturating_mul(scale)
+                            .saturating_mul(scale);
+        if scale == 0 || pixels > MAX_PIXELS {
+            return Err(format_err!("Can't draw {}x{} cells at scale {}",
+                                   columns, rows, scale));
+        }
+        Ok(Picture {
+            left: left,
+            top: top,
+            columns: columns,
+            rows: rows,
+            scale: scale,
+            buf: vec![0; pixels * 3],
+        })
+    }
+
+    fn width(&self) -> usize {
+        self.columns * self.scale
+    }
+
+    fn set(&mut self, column: usize, row: usize, color: [u8; 3]) {
+        let width = self.width();
+        for y in row * self.scale..(row + 1) * self.scale {
+            for x in column * self.scale..(column + 1) * self.scale {
+                let i = (y * width + x) * 3;
+                self.buf[i..i+3].copy_from_slice(&color);
+            }
         }
     }
 
-    fn manhattan_distance(p1: (i32, i32), p2: (i32, i32)) -> u32 {
-        ((p1.0-p2.0).abs() + (p1.1-p2.1).abs()) as u32
+    #[cfg(test)]
+    fn get(&self, x: i32, y: i32) -> [u8; 3] {
+        let (column, row) = ((x - self.left) as usize,
+                             (y - self.top) as usize);
+        let i = (row * self.scale * self.width() + column * self.scale) * 3;
+        [self.buf[i], self.buf[i + 1], self.buf[i + 2]]
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        image::save_buffer(path, &self.buf, self.width() as u32,
+                           (self.rows * self.scale) as u32, image::RGB(8))?;
+        Ok(())
+    }
+}
+
+impl AdventSolver for Solver {
+    fn params(&self) -> &'static [&'static str] {
+        &["metric", "threshold", "image", "scale", "margin"]
     }
 
-    fn read_coordinates() -> Result<Vec<(i32, i32)>, Error> {
+    fn solve(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        let coords = Self::read_coordinates(&ctx.input_path())?;
+        let metric = ctx.param("metric", "manhattan".to_string())?
+                        .parse::<Metric>()?;
+        let voronoi = Voronoi::new(&coords, metric)?;
+
+        let (largest, area) = voronoi.largest_finite().ok_or_else(|| {
+            format_err!("Every region is infinite")
+        })?;
+        println!("Largest finite area: coords[{}] {:?}: {}",
+                 largest[0], coords[largest[0]], area);
+        for &other in largest[1..].iter() {
+            println!("  (tied with coords[{}] {:?})", other, coords[other]);
+        }
+        ctx.answer(1, area);
+
+        if ctx.explaining() {
+            let cells = voronoi.cells(largest[0], &coords, metric);
+            let infinite: Vec<usize> =
+                (0..coords.len()).filter(|&i| voronoi.infinite[i]).collect();
+            ctx.explain("part1", Evidence::record()
+                .with("index", largest[0])
+                .with("coordinate", coords[largest[0]])
+                .with("area", area)
+                .with("tied_with", largest[1..].to_vec())
+                .with("areas", voronoi.areas.clone())
+                .with("infinite_regions", infinite)
+                .with("tied_cells", voronoi.tied)
+                .with("cells", cells));
+        }
+
+        // Part 2: Count positions with little enough total distance to coords
+        let threshold = ctx.param("threshold", 10000u64)?;
+        let region_size = Self::safe_region_size(&coords, metric, threshold);
+        println!("Area with locations with total distance < {}: {}",
+                 threshold, region_size);
+        ctx.answer(2, region_size);
+        ctx.explain("part2", Evidence::record()
+            .with("threshold", threshold)
+            .with("region_size", region_size));
+
+        let image = PathBuf::from(ctx.param("image", String::new())?);
+        if !image.as_os_str().is_empty() {
+            let picture = Self::draw(&coords, &voronoi, metric, threshold,
+                                     &largest, ctx.param("margin", 0usize)?,
+                                     ctx.param("scale", 2usize)?)?;
+            picture.save(&image)?;
+            println!("Regions saved to {} ({} cells out from the \
+                      coordinates, {} pixels to a cell).",
+                     image.display(), ctx.param("margin", 0usize)?,
+                     picture.scale);
+        }
+        Ok(())
+    }
+}
+
+impl Solver {
+    // Every region in its own colour (darker if it's infinite, gold for the
+    // largest finite one), grey where there's a tie, the coordinates in
+    // black, and the edge of the part 2 region in white. Covers the
+    // coordinates' box and `margin` cells around it.
+    fn draw(coords: &[(i32, i32)], voronoi: &Voronoi, metric: Metric,
+            threshold: u64, winners: &[usize], margin: usize, scale: usize)
+            -> Result<Picture, Error> {
+        let margin = margin.min(1 << 16);
+        let mut picture = Picture::new(voronoi.min_x - margin as i32,
+                                       voronoi.min_y - margin as i32,
+                                       voronoi.width + 2 * margin,
+                                       voronoi.height + 2 * margin,
+                                       scale)?;
+        let safe = |x: i32, y: i32| {
+            coords.iter()
+                  .map(|&c| metric.distance((x, y), c))
+                  .sum::<u64>() < threshold
+        };
+        for row in 0..picture.rows {
+            for column in 0..picture.columns {
+                let (x, y) = (picture.left + column as i32,
+                              picture.top + row as i32);
+                let color = match voronoi.owner_anywhere((x, y), coords,
+                                                         metric) {
+                    Owner::Coord(i) if coords[i] == (x, y) => COORDINATE,
+                    _ if safe(x, y) &&
+                         !(safe(x - 1, y) && safe(x + 1, y) &&
+                           safe(x, y - 1) && safe(x, y + 1)) => SAFE_EDGE,
+                    Owner::Coord(i) if winners.contains(&i) => WINNER,
+                    Owner::Coord(i) => region_color(i, voronoi.infinite[i]),
+                    Owner::Tied => TIED,
+                };
+                picture.set(column, row, color);
+            }
+        }
+        Ok(picture)
+    }
+
+    // How many cells have a total distance to the coordinates under the
+    // threshold, wherever they are.
+    //
+    // For the metrics that add up separately along each axis, the total for
+    // (x, y) is the total along x plus the total along y. Each of those is
+    // convex, so the positions where it's under the threshold are a run
+    // that can be walked out from its lowest point, and then it's a matter
+    // of counting pairs of them that add up to under the threshold.
+    // Chebyshev distance adds up the same way turned 45 degrees: it's half
+    // the Manhattan distance along the diagonals x + y and x - y, which
+    // only meet at cells where the two have the same parity.
+    fn safe_region_size(coords: &[(i32, i32)], metric: Metric,
+                        threshold: u64) -> u64 {
+        if coords.is_empty() {
+            return 0;
+        }
+        let (x_cost, y_cost) = match metric {
+            Metric::Manhattan | Metric::Chebyshev => {
+                (AxisCost::Linear(1), AxisCost::Linear(1))
+            },
+            Metric::WeightedManhattan(wx, wy) => {
+                (AxisCost::Linear(wx), AxisCost::Linear(wy))
+            },
+            Metric::SquaredEuclidean => (AxisCost::Square, AxisCost::Square),
+        };
+        let (xs, ys, threshold): (Vec<i64>, Vec<i64>, i128) = match metric {
+            Metric::Chebyshev => {
+                (coords.iter().map(|c| c.0 as i64 + c.1 as i64).collect(),
+                 coords.iter().map(|c| c.0 as i64 - c.1 as i64).collect(),
+                 2 * threshold as i128)
+            },
+            _ => {
+                (coords.iter().map(|c| c.0 as i64).collect(),
+                 coords.iter().map(|c| c.1 as i64).collect(),
+                 threshold as i128)
+            },
+        };
+        let columns = AxisTotals::new(&xs, x_cost);
+        let rows = AxisTotals::new(&ys, y_cost);
+        // The rows from top to bottom that go with a column.
+        let count = |x: i128, top: i128, bottom: i128| {
+            if metric == Metric::Chebyshev {
+                let parity = x.rem_euclid(2);
+                ((bottom - parity).div_euclid(2) -
+                 (top - 1 - parity).div_euclid(2)) as u64
+            } else {
+                (bottom - top + 1) as u64
+            }
+        };
+        let (lowest_x, lowest_y) = (columns.lowest(), rows.lowest());
+        let (left, right) =
+            match columns.run_under(threshold - rows.total(lowest_y)) {
+                Some(run) => run,
+                None => return 0,
+            };
+        // Going out from the lowest column either way, each column's total
+        // is at least the last one's, so its run of rows is inside the
+        // last one's.
+        let mut size = 0;
+        for &(from, to, step) in [(lowest_x, right, 1),
+                                  (lowest_x - 1, left, -1)].iter() {
+            let budget = |x: i128| threshold - columns.total(x);
+            let (mut top, mut bottom) = match rows.run_under(budget(from)) {
+                Some(run) => run,
+                None => continue,
+            };
+            let mut x = from;
+            while (to - x) * step >= 0 {
+                let budget = budget(x);
+                while rows.total(top) >= budget {
+                    top += 1;
+                }
+                while rows.total(bottom) >= budget {
+                    bottom -= 1;
+                }
+                size += count(x, top, bottom);
+                x += step;
+            }
+        }
+        size
+    }
+
+    fn read_coordinates(path: &Path) -> Result<Vec<(i32, i32)>, Error> {
         let re = Regex::new(r"(?P<x>\d+), (?P<y>\d+)").unwrap();
-        BufReader::new(File::open("input/day06.txt")?)
+        BufReader::new(File::open(path)?)
                   .lines()
                   .collect::<Result<Vec<String>, _>>()?
                   .iter()
@@ -148,3 +879,236 @@ impl Solver {
                   .collect::<Result<Vec<(i32,i32)>, _>>()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{region_color, Metric, Owner, Solver, Voronoi, COORDINATE,
+                SAFE_EDGE, TIED, WINNER};
+    use util::prop;
+    use util::rng::Rng;
+
+    const EXAMPLE: [(i32, i32); 6] =
+        [(1, 1), (1, 6), (8, 3), (3, 4), (5, 5), (8, 9)];
+
+    const METRICS: [Metric; 4] = [Metric::Manhattan, Metric::Chebyshev,
+                                  Metric::SquaredEuclidean,
+                                  Metric::WeightedManhattan(3, 1)];
+
+    #[test]
+    fn example_regions() {
+        let voronoi = Voronoi::new(&EXAMPLE, Metric::Manhattan).unwrap();
+        assert_eq!(vec![true, true, true, false, false, true],
+                   voronoi.infinite);
+        assert_eq!(9, voronoi.areas[3]);
+        assert_eq!(Some((vec![4], 17)), voronoi.largest_finite());
+        assert_eq!(Owner::Tied, voronoi.owner(1, 4));
+        assert_eq!(Owner::Tied, voronoi.owner(5, 1));
+    }
+
+    #[test]
+    fn ties_between_regions() {
+        // The example next to its mirror image.
+        let mut coords = EXAMPLE.to_vec();
+        coords.extend(EXAMPLE.iter().map(|&(x, y)| (20 - x, y)));
+        let voronoi = Voronoi::new(&coords, Metric::Manhattan).unwrap();
+        assert_eq!(Some((vec![4, 10], 17)), voronoi.largest_finite());
+        // The same place twice: neither gets it.
+        for &metric in METRICS.iter() {
+            let voronoi = Voronoi::new(&[(0, 0), (2, 2), (2, 2)], metric)
+                                  .unwrap();
+            assert_eq!(Owner::Tied, voronoi.owner(2, 2));
+            assert_eq!(vec![false, false], voronoi.infinite[1..].to_vec());
+        }
+    }
+
+    #[test]
+    fn metrics() {
+        let (a, b) = ((1, 2), (4, -2));
+        assert_eq!(vec![7, 4, 25, 13],
+                   METRICS.iter()
+                          .map(|m| m.distance(a, b))
+                          .collect::<Vec<u64>>());
+        assert_eq!(Metric::WeightedManhattan(2, 5),
+                   "weighted:2,5".parse::<Metric>().unwrap());
+        assert!("weighted:0,1".parse::<Metric>().is_err());
+        assert!("weighted:1".parse::<Metric>().is_err());
+        assert!("taxicab".parse::<Metric>().is_err());
+    }
+
+    #[test]
+    fn example_regions_by_metric() {
+        // (1, 1), (1, 6), (8, 3) and (8, 9) are the corners of the hull,
+        // and (3, 4) and (5, 5) are inside.
+        let voronoi = Voronoi::new(&EXAMPLE, Metric::SquaredEuclidean)
+                              .unwrap();
+        assert_eq!(vec![true, true, true, false, false, true],
+                   voronoi.infinite);
+        // In a line, everything's on the hull.
+        let voronoi = Voronoi::new(&[(0, 0), (2, 1), (4, 2)],
+                                   Metric::SquaredEuclidean).unwrap();
+        assert_eq!(vec![true, true, true], voronoi.infinite);
+        // Just inside the hull: the region reaches well out of the box.
+        let coords = [(0, 0), (10, 0), (5, 10), (5, 1)];
+        let voronoi = Voronoi::new(&coords, Metric::SquaredEuclidean)
+                              .unwrap();
+        assert_eq!((false, 59), (voronoi.infinite[3], voronoi.areas[3]));
+        assert_eq!(59, voronoi.cells(3, &coords, Metric::SquaredEuclidean)
+                              .len());
+        let voronoi = Voronoi::new(&coords, Metric::Chebyshev).unwrap();
+        assert_eq!((false, 45), (voronoi.infinite[3], voronoi.areas[3]));
+    }
+
+    fn coordinates(rng: &mut Rng, size: usize) -> Vec<(i32, i32)> {
+        (0..rng.below(size + 1) + 1)
+            .map(|_| (rng.below(7) as i32, rng.below(7) as i32))
+            .collect()
+    }
+
+    #[test]
+    fn labels_match_nearest_point() {
+        for &metric in METRICS.iter() {
+            prop::check("every cell goes to its unique nearest coordinate",
+                        coordinates,
+                        prop::shrink_vec,
+                        |coords| {
+                            if coords.is_empty() {
+                                return true;
+                            }
+                            let voronoi = Voronoi::new(coords, metric)
+                                                  .unwrap();
+                            let (x0, y0) = (voronoi.min_x, voronoi.min_y);
+                            (x0..x0 + voronoi.width as i32).all(|x| {
+                                (y0..y0 + voronoi.height as i32).all(|y| {
+                                    voronoi.owner(x, y) ==
+                                        match metric.nearest((x, y), coords) {
+                                            Some(i) => Owner::Coord(i),
+                                            None => Owner::Tied,
+                                        }
+                                })
+                            })
+                        });
+        }
+    }
+
+    #[test]
+    fn areas_match_counting_cells() {
+        // Finite regions can reach well outside the box, but not this far.
+        for &metric in METRICS.iter() {
+            prop::check("finite region sizes match counting every cell",
+                        coordinates,
+                        prop::shrink_vec,
+                        |coords| {
+                            if coords.is_empty() {
+                                return true;
+                            }
+                            let voronoi = Voronoi::new(coords, metric)
+                                                  .unwrap();
+                            let mut areas = vec![0; coords.len()];
+                            for x in -40..47 {
+                                for y in -40..47 {
+                                    if let Some(i) = metric.nearest((x, y),
+                                                                    coords) {
+                                        areas[i] += 1;
+                                    }
+                                }
+                            }
+                            (0..coords.len())
+                                .filter(|&i| !voronoi.infinite[i])
+                                .all(|i| voronoi.areas[i] == areas[i])
+                        });
+        }
+    }
+
+    #[test]
+    fn infinite_regions_reach_far_away() {
+        // A region is infinite just when it owns cells a long way out.
+        for &metric in METRICS.iter() {
+            prop::check("regions found infinite go on forever",
+                        coordinates,
+                        prop::shrink_vec,
+                        |coords| {
+                            if coords.is_empty() {
+                                return true;
+                            }
+                            let voronoi = Voronoi::new(coords, metric)
+                                                  .unwrap();
+                            let mut far = vec![false; coords.len()];
+                            let r = 500;
+                            for i in -r..=r {
+                                for &p in [(i, -r), (i, r), (-r, i), (r, i)]
+                                              .iter() {
+                                    if let Some(i) = metric.nearest(p, coords) {
+                                        far[i] = true;
+                                    }
+                                }
+                            }
+                            far == voronoi.infinite
+                        });
+        }
+    }
+
+    #[test]
+    fn safe_region_example() {
+        assert_eq!(16, Solver::safe_region_size(&EXAMPLE, Metric::Manhattan,
+                                                32));
+        assert_eq!(0, Solver::safe_region_size(&EXAMPLE, Metric::Manhattan,
+                                               0));
+    }
+
+    #[test]
+    fn safe_region_matches_counting_cells() {
+        // Thresholds big enough to go well outside the box.
+        for &metric in METRICS.iter() {
+            prop::check("safe region size matches summing every cell",
+                        |rng, size| {
+                            // Keeps the region within the cells counted.
+                            let coords = coordinates(rng, size / 5);
+                            let most = 50 * coords.len();
+                            (coords, rng.below(most + 1) as u64)
+                        },
+                        |&(ref coords, threshold)| {
+                            prop::shrink_vec(coords)
+                                .into_iter()
+                                .map(|coords| (coords, threshold))
+                                .collect()
+                        },
+                        |&(ref coords, threshold)| {
+                            if coords.is_empty() {
+                                return true;
+                            }
+                            let mut size = 0;
+                            for x in -60..70 {
+                                for y in -60..70 {
+                                    let total = coords.iter()
+                                        .map(|&c| metric.distance((x, y), c))
+                                        .sum::<u64>();
+                                    if total < threshold {
+                                        size += 1;
+                                    }
+                                }
+                            }
+                            Solver::safe_region_size(coords, metric,
+                                                     threshold) == size
+                        });
+        }
+    }
+
+    #[test]
+    fn drawing() {
+        let voronoi = Voronoi::new(&EXAMPLE, Metric::Manhattan).unwrap();
+        let picture = Solver::draw(&EXAMPLE, &voronoi, Metric::Manhattan, 32,
+                                   &[4], 1, 3).unwrap();
+        // The box is 8x9 cells, and there's one more all round.
+        assert_eq!((30, 33),
+                   (picture.width(), picture.buf.len() / 3 / picture.width()));
+        assert_eq!(COORDINATE, picture.get(5, 5));
+        assert_eq!(WINNER, picture.get(5, 4));
+        assert_eq!(SAFE_EDGE, picture.get(6, 5));
+        assert_eq!(TIED, picture.get(1, 4));
+        // Outside the box, and infinite.
+        assert_eq!(region_color(0, true), picture.get(0, 0));
+        assert_eq!(region_color(3, false), picture.get(2, 3));
+        assert!(Solver::draw(&EXAMPLE, &voronoi, Metric::Manhattan, 32,
+                             &[4], 1, 0).is_err());
+    }
+}
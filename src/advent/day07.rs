@@ -1,22 +1,139 @@
-use advent::AdventSolver;
+use advent::{AdventSolver, Context};
 use failure::Error;
 use regex::Regex;
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
 use std::iter;
+use std::path::Path;
+use util::evidence::Evidence;
+
+lazy_static! {
+    static ref DEPENDENCY_REGEX: Regex = Regex::new(
+        r"^Step (\S+) must be finished before step (\S+) can begin\.$")
+        .unwrap();
+    static ref DURATION_REGEX: Regex = Regex::new(
+        r"^Step (\S+) takes (\d+) seconds?\.$").unwrap();
+    // The other format: `name: dep, dep` or `name (seconds): dep, dep`, or
+    // just the name for a step with nothing to wait for.
+    static ref TASK_REGEX: Regex = Regex::new(
+        r"^(?P<name>[^\s:(),]+)\s*(?:\((?P<time>\d+)\))?\s*(?::(?P<deps>.*))?$")
+        .unwrap();
+}
+
+// The steps, what each one has to wait for, and how long any of them take
+// if the input says.
+#[derive(Debug, Default)]
+struct Plan {
+    steps: BTreeMap<String, BTreeSet<String>>,
+    durations: HashMap<String, u32>,
+}
+
+impl Plan {
+    // Lines can be in either format, mixed as you like:
+    //
+    //   Step C must be finished before step A can begin.
+    //   Step C takes 30 seconds.
+    //   A (30): C, D
+    //   C
+    //
+    // Blank lines and lines starting with # are skipped.
+    fn parse(input: &str) -> Result<Plan, Error> {
+        let mut plan = Plan::default();
+        for (i, line) in input.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(caps) = DEPENDENCY_REGEX.captures(line) {
+                plan.step(&caps[1]);
+                plan.step(&caps[2]).insert(caps[1].to_string());
+            } else if let Some(caps) = DURATION_REGEX.captures(line) {
+                plan.step(&caps[1]);
+                plan.set_duration(i + 1, &caps[1], &caps[2])?;
+            } else if let Some(caps) = TASK_REGEX.captures(line) {
+                let name = &caps["name"];
+                plan.step(name);
+                if let Some(time) = caps.name("time") {
+                    plan.set_duration(i + 1, name, time.as_str())?;
+                }
+                let deps = caps.name("deps").map_or("", |deps| deps.as_str());
+                for dep in deps.split(',').map(|dep| dep.trim()) {
+                    if dep.is_empty() {
+                        continue;
+                    }
+                    if dep.contains(char::is_whitespace) {
+                        return Err(format_err!("line {}: bad step name: {}",
+                                               i + 1, dep));
+                    }
+                    plan.step(dep);
+                    plan.step(name).insert(dep.to_string());
+                }
+            } else {
+                return Err(format_err!("line {}: parse error: {}",
+                                       i + 1, line));
+            }
+        }
+        Ok(plan)
+    }
+
+    #[cfg(test)]
+    fn from_pairs(deps: &[(&str, &str)]) -> Plan {
+        let mut plan = Plan::default();
+        for &(before, after) in deps {
+            plan.step(before);
+            plan.step(after).insert(before.to_string());
+        }
+        plan
+    }
+
+    // The prerequisites of a step, adding the step if it's new.
+    fn step(&mut self, name: &str) -> &mut BTreeSet<String> {
+        self.steps.entry(name.to_string()).or_insert_with(BTreeSet::new)
+    }
+
+    fn set_duration(&mut self, line: usize, name: &str, seconds: &str)
+            -> Result<(), Error> {
+        let seconds = seconds.parse::<u32>()?;
+        match self.durations.insert(name.to_string(), seconds) {
+            Some(before) if before != seconds => {
+                Err(format_err!("line {}: step {} takes {} seconds, but it \
+                                 took {} before",
+                                line, name, seconds, before))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    // How long a step takes: what the input said, if anything, otherwise
+    // the puzzle's formula for a lone capital letter (A is 1, B is 2, ...),
+    // or the default for any other name. The overhead applies to the last
+    // two. Times are u64 so that adding up any number of u32 durations
+    // can't overflow.
+    fn duration(&self, step: &str, overhead: u32, default: u32) -> u64 {
+        if let Some(&seconds) = self.durations.get(step) {
+            return seconds as u64;
+        }
+        let mut chars = step.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c @ 'A'..='Z'), None) => overhead as u64 + (c as u64) - 64,
+            _ => overhead as u64 + default as u64,
+        }
+    }
+}
 
 #[derive(Default)]
 struct Worker {
-    task: Option<char>,
-    time_remaining: u32,
+    task: Option<usize>,
+    started: u64,
+    time_remaining: u64,
 }
 
 impl Worker {
-    fn assign(&mut self, task: char, overhead: u32) {
+    fn assign(&mut self, task: usize, time: u64, now: u64) {
         assert!(self.task.is_none());
         self.task = Some(task);
-        self.time_remaining = Self::task_time(task, overhead);
+        self.started = now;
+        self.time_remaining = time;
     }
 
     fn is_busy(&self) -> bool {
@@ -25,7 +142,7 @@ impl Worker {
 
     // Work for the given number of seconds. Returns Some(task) if the task
     // was completed, otherwise None.
-    fn work(&mut self, elapsed: u32) -> Option<char> {
+    fn work(&mut self, elapsed: u64) -> Option<usize> {
         if self.time_remaining <= elapsed {
             self.time_remaining = 0;
             self.task.take()
@@ -34,9 +151,34 @@ impl Worker {
             None
         }
     }
+}
+
+// One step's stint on a worker: it started at the beginning of second
+// `start`, and was done by `end`.
+struct Job {
+    step: String,
+    worker: usize,
+    start: u64,
+    end: u64,
+}
 
-    fn task_time(task: char, overhead: u32) -> u32 {
-        (task as u32) - 64 + overhead
+impl Job {
+    fn evidence(&self) -> Evidence {
+        Evidence::record()
+            .with("step", self.step.clone())
+            .with("worker", self.worker)
+            .with("start", self.start)
+            .with("end", self.end)
+    }
+}
+
+// Single-letter steps read best run together, as the puzzle writes them;
+// longer names need separating.
+fn order_string(order: &[String]) -> String {
+    if order.iter().all(|step| step.chars().count() == 1) {
+        order.concat()
+    } else {
+        order.join(",")
     }
 }
 
@@ -44,123 +186,297 @@ impl Worker {
 pub struct Solver;
 
 impl AdventSolver for Solver {
-    fn solve(&mut self) -> Result<(), Error> {
-        let instructions = Self::read_instructions()?;
-        println!("Instruction sequence (solo project): {}",
-                 Self::build_sleigh(&instructions, 1, 0).0);
-        println!("Time to complete with 5 workers: {}",
-                 Self::build_sleigh(&instructions, 5, 60).1);
+    fn params(&self) -> &'static [&'static str] {
+        &["workers", "step_overhead", "default_duration"]
+    }
+
+    fn solve(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        let plan = Self::read_plan(&ctx.input_path())?;
+        let default_duration = ctx.param("default_duration", 1)?;
+        let (solo_order, _, solo_jobs) =
+            Self::build_sleigh(&plan, 1, 0, default_duration)?;
+        let solo_order = order_string(&solo_order);
+        println!("Instruction sequence (solo project): {}", solo_order);
+        ctx.answer(1, &solo_order);
+        let workers = ctx.param("workers", 5)?;
+        let step_overhead = ctx.param("step_overhead", 60)?;
+        let (_, time, jobs) = Self::build_sleigh(&plan, workers, step_overhead,
+                                                 default_duration)?;
+        println!("Time to complete with {} workers: {}", workers, time);
+        ctx.answer(2, time);
+
+        if ctx.explaining() {
+            ctx.explain("part1", Evidence::record()
+                .with("order", solo_order)
+                .with("timeline", Evidence::list(
+                    solo_jobs.iter().map(|job| job.evidence()))));
+            ctx.explain("part2", Evidence::record()
+                .with("workers", workers)
+                .with("step_overhead", step_overhead)
+                .with("total_time", time)
+                .with("timeline", Evidence::list(
+                    jobs.iter().map(|job| job.evidence()))));
+        }
         Ok(())
     }
 }
 
 impl Solver {
-    fn build_sleigh(instructions: &Vec<(char, char)>,
-                    num_workers: usize, step_overhead: u32) -> (String, u32) {
-        // Some steps may have no dependencies, so we'll only see them on the
-        // left-hand side, and others will have no steps that depend on them,
-        // so we'll only see them on the right-hand side. Collect them all.
-        let mut available_steps = HashSet::new();
-        for instruction in instructions.iter() {
-            available_steps.insert(instruction.0);
-            available_steps.insert(instruction.1);
+    // Returns the order the steps were finished in, how long it took, and
+    // the jobs that did it (in the same order as the steps).
+    fn build_sleigh(plan: &Plan, num_workers: usize, step_overhead: u32,
+                    default_duration: u32)
+            -> Result<(Vec<String>, u64, Vec<Job>), Error> {
+        if num_workers == 0 {
+            return Err(format_err!("Nobody to build the sleigh"));
         }
-        let mut done_steps = Vec::new();
+        // Steps are numbered in name order, so the lowest ready number is
+        // the one to start first.
+        let names: Vec<&String> = plan.steps.keys().collect();
+        let index: HashMap<&str, usize> =
+            names.iter().enumerate().map(|(i, name)| (name.as_str(), i))
+                 .collect();
+        let prereqs: Vec<Vec<usize>> =
+            plan.steps.values()
+                .map(|deps| deps.iter().map(|dep| index[dep.as_str()])
+                                .collect())
+                .collect();
+        let times: Vec<u64> =
+            names.iter()
+                 .map(|name| plan.duration(name, step_overhead,
+                                           default_duration))
+                 .collect();
+
+        let mut available_steps: BTreeSet<usize> = (0..names.len()).collect();
+        let mut done = vec![false; names.len()];
+        let mut done_steps: Vec<usize> = Vec::new();
+        let mut jobs: Vec<Job> = Vec::new();
         let mut workers: Vec<Worker> = iter::repeat_with(Worker::default)
                                             .take(num_workers)
                                             .collect();
 
-        // Build dependency graph
-        let mut depgraph: HashMap<char, Vec<char>> = HashMap::new();
-        for instruction in instructions {
-            let v = depgraph.entry(instruction.1).or_insert(Vec::new());
-            v.push(instruction.0);
-        }
-
-        // Loop steps forward one second at a time, assigning workers tasks
-        // whenever tasks and workers are available.
-        let mut seconds_elapsed: u32 = 0;
+        // Assign workers whatever's ready, then skip ahead to when the next
+        // worker finishes.
+        let mut seconds_elapsed: u64 = 0;
         while !available_steps.is_empty() ||
               workers.iter().any(|worker| worker.is_busy()) {
 
             // Determine work available
-            let mut ready_steps: Vec<char> = Vec::new();
-            for step in available_steps.iter() {
-                match depgraph.get(&step) {
-                    Some(prereqs) => {
-                        if prereqs.iter().all(|s| done_steps.contains(s)) {
-                            ready_steps.push(*step);
-                        }
-                    },
-                    None => {
-                        ready_steps.push(*step);
-                    }
-                }
-            }
+            let mut ready_steps: Vec<usize> =
+                available_steps.iter()
+                               .filter(|&&step| {
+                                   prereqs[step].iter().all(|&s| done[s])
+                               })
+                               .cloned()
+                               .collect();
 
             // Assign work
-            ready_steps.sort();
+            ready_steps.reverse();
             for worker in workers.iter_mut() {
-                if !worker.is_busy() && ready_steps.len() > 0 {
-                    let next_step = ready_steps.remove(0);
-                    worker.assign(next_step, step_overhead);
-                    available_steps.remove(&next_step);
+                if worker.is_busy() {
+                    continue;
                 }
+                let next_step = match ready_steps.pop() {
+                    Some(step) => step,
+                    None => break,
+                };
+                worker.assign(next_step, times[next_step], seconds_elapsed);
+                available_steps.remove(&next_step);
             }
 
             // Do work
-            for worker in workers.iter_mut() {
-                match worker.work(1) {
-                    Some(task) => done_steps.push(task),
-                    None => {}
+            let elapsed = match workers.iter()
+                                       .filter(|worker| worker.is_busy())
+                                       .map(|worker| worker.time_remaining)
+                                       .min() {
+                Some(elapsed) => elapsed,
+                None => {
+                    let stuck: Vec<&str> =
+                        available_steps.iter()
+                                       .map(|&step| names[step].as_str())
+                                       .collect();
+                    return Err(format_err!(
+                        "Steps can never start (there's a dependency \
+                         cycle): {}", stuck.join(", ")));
+                }
+            };
+            seconds_elapsed += elapsed;
+            for (i, worker) in workers.iter_mut().enumerate() {
+                if let Some(task) = worker.work(elapsed) {
+                    done[task] = true;
+                    done_steps.push(task);
+                    jobs.push(Job {
+                        step: names[task].clone(),
+                        worker: i,
+                        start: worker.started,
+                        end: seconds_elapsed,
+                    });
                 }
             }
-            seconds_elapsed += 1;
         }
-        (done_steps.iter().collect(), seconds_elapsed)
-    }
-
-    // Returns a vector of dependency tuples (a, b), where step A must be done
-    // before step B can begin.
-    fn read_instructions() -> Result<Vec<(char, char)>, Error> {
-        let re = Regex::new(
-            r"Step ([A-Z]) must be finished before step ([A-Z]) can begin.")?;
-        BufReader::new(File::open("input/day07.txt")?)
-                  .lines()
-                  .collect::<Result<Vec<String>, _>>()?
-                  .iter()
-                  .map(|line| {
-                      match re.captures(line) {
-                          Some(caps) => {
-                              Ok((caps[1].chars().nth(0).unwrap(),
-                                  caps[2].chars().nth(0).unwrap()))
-                          },
-                          None => Err(format_err!("Parse error: {}", line))
-                      }
-                  })
-                  .collect::<Result<Vec<(char, char)>, _>>()
+        let order = done_steps.iter().map(|&step| names[step].clone())
+                                     .collect();
+        Ok((order, seconds_elapsed, jobs))
+    }
+
+    fn read_plan(path: &Path) -> Result<Plan, Error> {
+        Plan::parse(&fs::read_to_string(path)?)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Solver;
+    use super::{order_string, Plan, Solver};
+    use std::collections::HashSet;
+    use util::prop;
+    use util::rng::Rng;
 
-    lazy_static! {
-        static ref DEPS: Vec<(char, char)> =
-            vec![('C', 'A'), ('C', 'F'), ('A', 'B'), ('A', 'D'),
-                 ('B', 'E'), ('D', 'E'), ('F', 'E')];
+    const DEPS: [(&str, &str); 7] =
+        [("C", "A"), ("C", "F"), ("A", "B"), ("A", "D"),
+         ("B", "E"), ("D", "E"), ("F", "E")];
+
+    fn order(plan: &Plan, workers: usize) -> String {
+        order_string(&Solver::build_sleigh(plan, workers, 0, 1).unwrap().0)
     }
 
     #[test]
     fn part1_example() {
-        let (build_order, _) = Solver::build_sleigh(&DEPS, 1, 0);
-        assert_eq!("CABDFE", build_order);
+        assert_eq!("CABDFE", order(&Plan::from_pairs(&DEPS), 1));
     }
 
     #[test]
     fn part2_example() {
-        let (_, time_spent) = Solver::build_sleigh(&DEPS, 2, 0);
+        let (_, time_spent, _) =
+            Solver::build_sleigh(&Plan::from_pairs(&DEPS), 2, 0, 1).unwrap();
         assert_eq!(15, time_spent);
     }
+
+    #[test]
+    fn task_per_line_format() {
+        // The example again, plus a step that nothing depends on.
+        let plan = Plan::parse("\
+            # The example
+            C:
+            A: C
+            F (60): C
+            B: A
+
+            D: A
+            E: B, D, F
+            G").unwrap();
+        assert_eq!("CABDFEG", order(&plan, 1));
+        assert_eq!((60, 1, 7), (plan.duration("F", 0, 1),
+                                plan.duration("A", 0, 1),
+                                plan.duration("G", 0, 1)));
+    }
+
+    #[test]
+    fn named_steps_and_durations() {
+        let plan = Plan::parse("\
+            Step fetch must be finished before step build can begin.
+            Step configure must be finished before step build can begin.
+            Step build takes 120 seconds.
+            test (30): build
+            configure (5):
+            package: build").unwrap();
+        assert_eq!("configure,fetch,build,package,test", order(&plan, 1));
+        // Without a duration, fetch and package take the default.
+        let (_, time_spent, jobs) =
+            Solver::build_sleigh(&plan, 2, 0, 10).unwrap();
+        assert_eq!(160, time_spent);
+        assert_eq!(vec![("configure", 0, 5), ("fetch", 0, 10),
+                        ("build", 10, 130), ("package", 130, 140),
+                        ("test", 130, 160)],
+                   jobs.iter()
+                       .map(|job| (job.step.as_str(), job.start, job.end))
+                       .collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn long_steps() {
+        // Well past what fits in a u32 second count.
+        let plan = Plan::parse("a (4000000000):\nb (4000000000): a").unwrap();
+        let (_, time_spent, _) = Solver::build_sleigh(&plan, 2, 0, 1).unwrap();
+        assert_eq!(8_000_000_000, time_spent);
+    }
+
+    #[test]
+    fn bad_plans() {
+        let error = |input| {
+            Plan::parse(input)
+                .and_then(|plan| Solver::build_sleigh(&plan, 2, 0, 1))
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!("line 2: step a takes 3 seconds, but it took 2 before",
+                   error("a (2):\nStep a takes 3 seconds."));
+        assert_eq!("line 1: parse error: Step A must be done before B.",
+                   error("Step A must be done before B."));
+        assert_eq!("Steps can never start (there's a dependency cycle): \
+                    a, b, c",
+                   error("a: c\nb: a\nc: b"));
+    }
+
+    // A random dependency graph: some of the letters in a random order, with
+    // edges only ever pointing forwards in that order so there are no cycles.
+    fn random_deps(rng: &mut Rng, size: usize) -> Vec<(String, String)> {
+        let mut steps: Vec<char> = (b'A'..=b'Z').map(|c| c as char).collect();
+        for i in (1..steps.len()).rev() {
+            let j = rng.below(i + 1);
+            steps.swap(i, j);
+        }
+        steps.truncate(2 + rng.below(size.min(24) + 1));
+        let mut deps = Vec::new();
+        for i in 0..steps.len() {
+            for j in i+1..steps.len() {
+                if rng.below(4) == 0 {
+                    deps.push((steps[i].to_string(), steps[j].to_string()));
+                }
+            }
+        }
+        deps
+    }
+
+    fn plan(deps: &Vec<(String, String)>) -> Plan {
+        Plan::from_pairs(&deps.iter()
+                              .map(|&(ref a, ref b)| (a.as_str(), b.as_str()))
+                              .collect::<Vec<_>>())
+    }
+
+    fn steps_in(deps: &Vec<(String, String)>) -> HashSet<String> {
+        deps.iter().flat_map(|&(ref a, ref b)| vec![a.clone(), b.clone()])
+                   .collect()
+    }
+
+    #[test]
+    fn build_order_is_a_topological_order() {
+        prop::check("every step runs once, after its prerequisites",
+                    random_deps,
+                    prop::shrink_vec,
+                    |deps| {
+                        let order = order(&plan(deps), 1);
+                        let position = |step: &str| order.find(step).unwrap();
+                        order.len() == steps_in(deps).len() &&
+                        steps_in(deps).iter().all(|s| order.contains(&**s)) &&
+                        deps.iter().all(|&(ref a, ref b)| {
+                            position(a) < position(b)
+                        })
+                    });
+    }
+
+    #[test]
+    fn solo_build_time_is_total_task_time() {
+        prop::check("one worker is never idle",
+                    random_deps,
+                    prop::shrink_vec,
+                    |deps| {
+                        let plan = plan(deps);
+                        let (_, time, _) =
+                            Solver::build_sleigh(&plan, 1, 0, 1).unwrap();
+                        time == steps_in(deps).iter()
+                                              .map(|s| plan.duration(s, 0, 1))
+                                              .sum::<u64>()
+                    });
+    }
 }
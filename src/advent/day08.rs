@@ -1,8 +1,10 @@
-use advent::AdventSolver;
+use advent::{AdventSolver, Context};
 use failure::Error;
 use std::fs::File;
 use std::io::Read;
 use std::iter::Iterator;
+use std::str;
+use util::evidence::Evidence;
 
 #[derive(Debug,Default)]
 struct Node {
@@ -25,83 +27,240 @@ impl Node {
 pub struct Solver;
 
 impl AdventSolver for Solver {
-    fn solve(&mut self) -> Result<(), Error> {
+    fn solve(&mut self, ctx: &mut Context) -> Result<(), Error> {
         // Load input
         let mut input = String::new();
-        File::open("input/day08.txt")?.read_to_string(&mut input)?;
-        input = input.trim().to_string();
-
-        let input_values = input.split_whitespace()
-                                .map(|s| s.parse::<usize>().unwrap())
-                                .collect::<Vec<usize>>();
-        let mut nodes = Self::read_nodes(&mut input_values.iter(), 0, 1);
-        // Sorting the vec by id allows us to index into it by id.
-        nodes.sort_by_key(|n| n.id);
+        File::open(ctx.input_path())?.read_to_string(&mut input)?;
+        let nodes = Self::parse_tree(&input)?;
 
         // Part 1
-        println!("Sum of metadata entries: {}",
-                 nodes.iter()
-                      .map(|n| n.metadata.iter().sum::<usize>())
-                      .sum::<usize>());
+        let metadata_sum = Self::metadata_sum(&nodes)?;
+        println!("Sum of metadata entries: {}", metadata_sum);
+        ctx.answer(1, metadata_sum);
 
         // Part 2
         let root_node = &nodes[0];
-        println!("Root node value: {}", Self::node_value(root_node, &nodes));
+        let values = Self::node_values(&nodes)?;
+        println!("Root node value: {}", values[root_node.id]);
+        ctx.answer(2, values[root_node.id]);
+
+        if ctx.explaining() {
+            ctx.explain("part1", Evidence::record()
+                .with("nodes", nodes.len())
+                .with("metadata_entries",
+                      nodes.iter().map(|n| n.metadata.len()).sum::<usize>())
+                .with("metadata_sum", metadata_sum));
+            ctx.explain("part2", Evidence::record()
+                .with("root_metadata", root_node.metadata.clone())
+                .with("child_values",
+                      root_node.children.iter()
+                                        .map(|&child| values[child])
+                                        .collect::<Vec<usize>>())
+                .with("root_value", values[root_node.id]));
+        }
         Ok(())
     }
 }
 
 impl Solver {
-    fn node_value(node: &Node, nodes: &Vec<Node>) -> usize {
-        if node.children.len() == 0 {
-            node.metadata.iter().sum()
-        } else {
-            node.metadata
-                .iter()
-                .map(|&m| {
-                    if m == 0 || m > node.children.len() {
-                        0
-                    } else {
-                        Self::node_value(&nodes[node.children[m-1]], nodes)
-                    }
-                })
-                .sum()
+    // Returns the nodes indexed by id; the root node is always nodes[0].
+    fn parse_tree(input: &str) -> Result<Vec<Node>, Error> {
+        let input_values = input.split_whitespace()
+                                .map(|s| s.parse::<usize>())
+                                .collect::<Result<Vec<usize>, _>>()?;
+        let mut nodes = Vec::new();
+        Self::read_nodes(&mut input_values.iter(), &mut nodes, 1)?;
+        Ok(nodes)
+    }
+
+    fn metadata_sum(nodes: &Vec<Node>) -> Result<usize, Error> {
+        Self::checked_sum(nodes.iter().flat_map(|n| n.metadata.iter().cloned()))
+    }
+
+    // Value of every node, indexed by id. Children always have higher ids
+    // than their parent, so filling the table back to front means a child's
+    // value is ready before its parent needs it. (Recursing instead goes
+    // exponential when metadata keeps referring to the same child.)
+    fn node_values(nodes: &Vec<Node>) -> Result<Vec<usize>, Error> {
+        let mut values = vec![0; nodes.len()];
+        for node in nodes.iter().rev() {
+            values[node.id] = if node.children.len() == 0 {
+                Self::checked_sum(node.metadata.iter().cloned())?
+            } else {
+                Self::checked_sum(
+                    node.metadata
+                        .iter()
+                        .map(|&m| {
+                            if m == 0 || m > node.children.len() {
+                                0
+                            } else {
+                                values[node.children[m-1]]
+                            }
+                        }))?
+            };
         }
+        Ok(values)
+    }
+
+    fn checked_sum<T: Iterator<Item=usize>>(mut values: T)
+            -> Result<usize, Error> {
+        values.try_fold(0usize, |acc, v| acc.checked_add(v))
+              .ok_or_else(|| format_err!("Sum overflowed"))
     }
 
+    // Reads `num_nodes` sibling nodes, and everything beneath them, onto the
+    // end of `nodes`. Ids are handed out in the order the nodes' headers
+    // appear, so a node's id is also its index in `nodes`. Returns the ids of
+    // the siblings.
     fn read_nodes<'a, T: Iterator<Item=&'a usize>>(
-            input: &mut T, next_id: usize, num_nodes: usize) -> Vec<Node> {
-        let mut result = Vec::new();
-        let mut next_id = next_id;
-        let mut children_to_append: Vec<Node> = Vec::new();
+            input: &mut T, nodes: &mut Vec<Node>, num_nodes: usize)
+            -> Result<Vec<usize>, Error> {
+        let mut ids = Vec::new();
+        // Header counts can promise far more than the input holds, so these
+        // loops rely on next_value() bailing out at the end of the input.
         for _ in 0..num_nodes {
-            match input.next() {
-                Some(n) => {
-                    let mut node = Node::new(next_id);
-                    next_id += 1;
-                    let num_children = *n;
-                    let num_metadata = *(input.next().unwrap());
-                    if num_children > 0 {
-                        let mut children =
-                            Self::read_nodes(input, next_id, num_children);
-                        next_id = children.iter()
-                                          .max_by_key(|c| c.id)
-                                          .unwrap().id + 1;
-                        for child in children.iter().take(num_children) {
-                            node.children.push(child.id);
-                        }
-                        children_to_append.append(&mut children);
-                    }
-                    for _ in 0..num_metadata {
-                        node.metadata.push(*(input.next().unwrap()));
-                    }
-                    result.push(node);
-                },
-                None => {}
+            let num_children = *Self::next_value(input)?;
+            let num_metadata = *Self::next_value(input)?;
+            let id = nodes.len();
+            nodes.push(Node::new(id));
+            let children = Self::read_nodes(input, nodes, num_children)?;
+            let mut metadata = Vec::new();
+            for _ in 0..num_metadata {
+                metadata.push(*Self::next_value(input)?);
             }
+            nodes[id].children = children;
+            nodes[id].metadata = metadata;
+            ids.push(id);
         }
-        result.append(&mut children_to_append);
-        result
+        Ok(ids)
+    }
+
+    fn next_value<'a, T: Iterator<Item=&'a usize>>(input: &mut T)
+            -> Result<&'a usize, Error> {
+        input.next()
+             .ok_or_else(|| format_err!("Input ended inside a node"))
+    }
+
+    // Fuzz target: tree parsing alone.
+    pub fn fuzz_parse(data: &[u8]) -> Result<(), Error> {
+        Self::parse_tree(str::from_utf8(data)?)?;
+        Ok(())
+    }
+
+    // Fuzz target: parsing plus both parts.
+    pub fn fuzz_solve(data: &[u8]) -> Result<(), Error> {
+        let nodes = Self::parse_tree(str::from_utf8(data)?)?;
+        Self::metadata_sum(&nodes)?;
+        Self::node_values(&nodes)?;
+        Ok(())
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::{Node, Solver};
+    use util::prop;
+    use util::rng::Rng;
+
+    #[derive(Clone, Debug)]
+    struct Tree {
+        children: Vec<Tree>,
+        metadata: Vec<usize>,
+    }
+
+    impl Tree {
+        fn encode(&self, out: &mut Vec<String>) {
+            out.push(self.children.len().to_string());
+            out.push(self.metadata.len().to_string());
+            for child in self.children.iter() {
+                child.encode(out);
+            }
+            out.extend(self.metadata.iter().map(|m| m.to_string()));
+        }
+
+        fn sum(&self) -> usize {
+            self.metadata.iter().sum::<usize>() +
+            self.children.iter().map(|child| child.sum()).sum::<usize>()
+        }
+
+        // The puzzle's definition, recursing as often as it says to.
+        fn value(&self) -> usize {
+            if self.children.is_empty() {
+                return self.metadata.iter().sum();
+            }
+            self.metadata.iter()
+                         .filter(|&&m| m >= 1 && m <= self.children.len())
+                         .map(|&m| self.children[m-1].value())
+                         .sum()
+        }
+    }
+
+    // A tree of about `size` nodes, with metadata that sometimes points
+    // past the children.
+    fn random_tree(rng: &mut Rng, size: usize) -> Tree {
+        let mut children = Vec::new();
+        let mut budget = rng.below(size + 1);
+        while budget > 0 {
+            let child_size = 1 + rng.below(budget);
+            children.push(random_tree(rng, child_size - 1));
+            budget -= child_size;
+        }
+        let metadata = (0..rng.below(4))
+                           .map(|_| rng.below(children.len() + 3))
+                           .collect();
+        Tree { children: children, metadata: metadata }
+    }
+
+    // Any one subtree, or this one with a child or an entry dropped.
+    fn shrink_tree(tree: &Tree) -> Vec<Tree> {
+        let mut result = tree.children.clone();
+        for i in 0..tree.children.len() {
+            let mut smaller = tree.clone();
+            smaller.children.remove(i);
+            result.push(smaller);
+        }
+        for i in 0..tree.metadata.len() {
+            let mut smaller = tree.clone();
+            smaller.metadata.remove(i);
+            result.push(smaller);
+        }
+        result
+    }
+
+    fn parse(tree: &Tree) -> Vec<Node> {
+        let mut values = Vec::new();
+        tree.encode(&mut values);
+        Solver::parse_tree(&values.join(" ")).unwrap()
+    }
+
+    #[test]
+    fn example() {
+        let nodes = Solver::parse_tree(
+            "2 3 0 3 10 11 12 1 1 0 1 99 2 1 1 2").unwrap();
+        assert_eq!(138, Solver::metadata_sum(&nodes).unwrap());
+        assert_eq!(66, Solver::node_values(&nodes).unwrap()[0]);
+    }
+
+    #[test]
+    fn metadata_sum_matches_a_tree_walk() {
+        prop::check("metadata sum is every node's metadata",
+                    random_tree,
+                    shrink_tree,
+                    |tree| {
+                        Solver::metadata_sum(&parse(tree)).unwrap() ==
+                            tree.sum()
+                    });
+    }
+
+    #[test]
+    fn node_values_match_recursion() {
+        prop::check("table of values matches the recursive definition",
+                    random_tree,
+                    shrink_tree,
+                    |tree| {
+                        Solver::node_values(&parse(tree)).unwrap()[0] ==
+                            tree.value()
+                    });
+    }
+}
@@ -1,8 +1,9 @@
-use advent::AdventSolver;
+use advent::{AdventSolver, Context};
 use failure::Error;
 use std::collections::VecDeque;
+use util::evidence::Evidence;
 
-// My input:
+// My input (override with --param players=N --param last_marble=N):
 const NUM_PLAYERS: usize = 470;
 const LAST_MARBLE_VALUE: usize = 72170;
 
@@ -10,17 +11,56 @@ const LAST_MARBLE_VALUE: usize = 72170;
 pub struct Solver;
 
 impl AdventSolver for Solver {
-    fn solve(&mut self) -> Result<(), Error> {
-        println!("Winning score with last marble {}: {}", LAST_MARBLE_VALUE,
-                 Self::play_game(NUM_PLAYERS, LAST_MARBLE_VALUE));
-        println!("Winning score with last marble {}: {}", LAST_MARBLE_VALUE*100,
-                 Self::play_game(NUM_PLAYERS, LAST_MARBLE_VALUE*100));
+    fn params(&self) -> &'static [&'static str] {
+        &["players", "last_marble"]
+    }
+
+    fn solve(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        let num_players = ctx.param("players", NUM_PLAYERS)?;
+        let last_marble_value = ctx.param("last_marble", LAST_MARBLE_VALUE)?;
+        if num_players == 0 {
+            return Err(format_err!("Need at least one player"));
+        }
+        for part in 1..=2 {
+            let last_marble = if part == 1 { last_marble_value }
+                              else { last_marble_value*100 };
+            let scores = Self::play_scores(num_players, last_marble);
+            let (winner, score) = Self::winner(&scores);
+            println!("Winning score with last marble {}: {}", last_marble,
+                     score);
+            ctx.answer(part, score);
+            ctx.explain(&format!("part{}", part), Evidence::record()
+                .with("players", num_players)
+                .with("last_marble", last_marble)
+                .with("winning_player", winner)
+                .with("winning_score", score));
+        }
         Ok(())
     }
 }
 
 impl Solver {
+    // Returns the winning score.
+    #[cfg(test)]
     fn play_game(num_players: usize, last_marble_value: usize) -> usize {
+        Self::winner(&Self::play_scores(num_players, last_marble_value)).1
+    }
+
+    // The winning player (numbered from 1, the first if there's a tie) and
+    // their score.
+    fn winner(scores: &[usize]) -> (usize, usize) {
+        let mut best = (1, scores[0]);
+        for (i, &score) in scores.iter().enumerate() {
+            if score > best.1 {
+                best = (i + 1, score);
+            }
+        }
+        best
+    }
+
+    // Returns every player's final score.
+    fn play_scores(num_players: usize, last_marble_value: usize)
+            -> Vec<usize> {
         let mut circle: VecDeque<isize> = VecDeque::new();
         let mut scores: Vec<usize> = (0..num_players).map(|_| 0).collect();
         circle.push_back(0);
@@ -48,13 +88,14 @@ impl Solver {
             current_player = (current_player + 1) % num_players;
             current_marble += 1;
         }
-        *(scores.iter().max().unwrap())
+        scores
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::Solver;
+    use util::prop;
 
     #[test]
     fn test_part1_examples() {
@@ -66,4 +107,24 @@ mod tests {
         assert_eq!(54718, Solver::play_game(21, 6111));
         assert_eq!(37305, Solver::play_game(30, 5807));
     }
+
+    #[test]
+    fn one_scoring_marble_credits_one_player() {
+        // Marble 23 scores itself plus marble 9, no matter how many players
+        // there are, and the next scoring marble isn't until 46.
+        prop::check("exactly one player scores with marbles up to 45",
+                    |rng, size| (1 + rng.below(size + 1), 23 + rng.below(23)),
+                    |&(players, last)| {
+                        prop::shrink_usize(&players)
+                            .into_iter()
+                            .filter(|&p| p > 0)
+                            .map(|p| (p, last))
+                            .collect()
+                    },
+                    |&(players, last)| {
+                        let scores = Solver::play_scores(players, last);
+                        scores.iter().filter(|&&s| s > 0).count() == 1 &&
+                        scores.iter().sum::<usize>() == 32
+                    });
+    }
 }
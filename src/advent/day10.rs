@@ -1,10 +1,11 @@
-use advent::AdventSolver;
+use advent::{AdventSolver, Context};
 use failure::Error;
 use regex::Regex;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
+use util::evidence::Evidence;
 
 #[derive(Clone,Copy,Debug)]
 struct Point {
@@ -25,8 +26,8 @@ impl Point {
 pub struct Solver;
 
 impl AdventSolver for Solver {
-    fn solve(&mut self) -> Result<(), Error> {
-        let points = Self::read_points()?;
+    fn solve(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        let points = Self::read_points(&ctx.input_path())?;
         let mut connectedness_history: Vec<f64> = Vec::new();
         for t in 0.. {
             let connectedness = Self::measure_connectedness(&points, t);
@@ -38,6 +39,18 @@ impl AdventSolver for Solver {
                 println!("At t={}, connectedness is {} stddev above average!",
                          t, (connectedness-mean)/stddev);
                 Self::draw_points(&points, t)?;
+                // Part 1's answer is in the picture, for a human to read.
+                ctx.answer(2, t);
+                let ((minx, miny), (maxx, maxy)) = Self::bounds(&points, t);
+                ctx.explain("part2", Evidence::record()
+                    .with("time", t)
+                    .with("points", points.len())
+                    .with("connectedness", format!("{:.3}", connectedness))
+                    .with("mean_before", format!("{:.3}", mean))
+                    .with("stddevs_above",
+                          format!("{:.1}", (connectedness-mean)/stddev))
+                    .with("top_left", (minx, miny))
+                    .with("size", (maxx - minx + 1, maxy - miny + 1)));
                 break;
             }
             connectedness_history.push(connectedness);
@@ -49,13 +62,10 @@ impl AdventSolver for Solver {
 impl Solver {
 
     fn draw_points(points: &Vec<Point>, t: i64) -> Result<(), Error> {
+        let ((minx, miny), (maxx, maxy)) = Self::bounds(points, t);
         let points: Vec<(i64, i64)> = points.iter()
                                             .map(|p| p.position_at_time(t))
                                             .collect();
-        let minx = points.iter().min_by_key(|&(x, _)| x).unwrap().0;
-        let miny = points.iter().min_by_key(|&(_, y)| y).unwrap().1;
-        let maxx = points.iter().max_by_key(|&(x, _)| x).unwrap().0;
-        let maxy = points.iter().max_by_key(|&(_, y)| y).unwrap().1;
         let width = (maxx - minx + 1) as usize;
         let height = (maxy - miny + 1) as usize;
         let xoff = -minx;
@@ -91,6 +101,18 @@ impl Solver {
         Ok(())
     }
 
+    // The top left and bottom right corners of the points at time t.
+    fn bounds(points: &Vec<Point>, t: i64) -> ((i64, i64), (i64, i64)) {
+        let points: Vec<(i64, i64)> = points.iter()
+                                            .map(|p| p.position_at_time(t))
+                                            .collect();
+        let minx = points.iter().min_by_key(|&(x, _)| x).unwrap().0;
+        let miny = points.iter().min_by_key(|&(_, y)| y).unwrap().1;
+        let maxx = points.iter().max_by_key(|&(x, _)| x).unwrap().0;
+        let maxy = points.iter().max_by_key(|&(_, y)| y).unwrap().1;
+        ((minx, miny), (maxx, maxy))
+    }
+
     // Returns the average number of neighbors (max 4) of each point.
     fn measure_connectedness(points: &Vec<Point>, t: i64) -> f64 {
         let index: HashMap<(i64,i64), &Point> =
@@ -108,12 +130,12 @@ impl Solver {
               .sum::<f64>() / points.len() as f64
     }
 
-    fn read_points() -> Result<Vec<Point>, Error> {
+    fn read_points(path: &Path) -> Result<Vec<Point>, Error> {
         let re = Regex::new(
             r"(?x)
               position=<\s*(-?\d+),\s*(-?\d+)>\s
               velocity=<\s*(-?\d+),\s*(-?\d+)>")?;
-        BufReader::new(File::open("input/day10.txt")?)
+        BufReader::new(File::open(path)?)
                   .lines()
                   .collect::<Result<Vec<String>, _>>()?
                   .iter()
@@ -140,3 +162,48 @@ impl Solver {
                .sum::<f64>() / values.len() as f64) as f64).sqrt()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Point, Solver};
+    use util::prop;
+    use util::rng::Rng;
+
+    // Points crowded into a small box, so plenty of them end up adjacent.
+    fn random_points(rng: &mut Rng, size: usize) -> Vec<Point> {
+        (0..1 + rng.below(size + 1))
+            .map(|_| Point {
+                px: rng.below(8) as i64,
+                py: rng.below(8) as i64,
+                vx: rng.below(3) as i64 - 1,
+                vy: rng.below(3) as i64 - 1,
+            })
+            .collect()
+    }
+
+    fn brute_force(points: &Vec<Point>, t: i64) -> f64 {
+        let positions: Vec<(i64, i64)> =
+            points.iter().map(|p| p.position_at_time(t)).collect();
+        let neighbors = positions.iter().map(|&(x, y)| {
+            [(x-1, y), (x+1, y), (x, y-1), (x, y+1)]
+                .iter()
+                .filter(|n| positions.contains(n))
+                .count()
+        });
+        neighbors.sum::<usize>() as f64 / points.len() as f64
+    }
+
+    #[test]
+    fn connectedness_counts_neighbors() {
+        prop::check("connectedness matches checking every pair",
+                    random_points,
+                    prop::shrink_vec,
+                    |points| {
+                        points.is_empty() ||
+                        (0..4).all(|t| {
+                            Solver::measure_connectedness(points, t) ==
+                                brute_force(points, t)
+                        })
+                    });
+    }
+}
@@ -1,21 +1,40 @@
-use advent::AdventSolver;
+use advent::{AdventSolver, Context};
 use failure::Error;
+use util::evidence::Evidence;
 
+// My input (override with --param serial=N):
 const SERIAL_NO: i64 = 4172;
 
 #[derive(Default)]
 pub struct Solver;
 
 impl AdventSolver for Solver {
-    fn solve(&mut self) -> Result<(), Error> {
-        println!("Max 3x3 square: {:?}",
-                 find_largest_total_power(SERIAL_NO, 3, 3));
-        println!("Max NxN square: {:?}",
-                 find_largest_total_power(SERIAL_NO, 1, 300));
+    fn params(&self) -> &'static [&'static str] {
+        &["serial"]
+    }
+
+    fn solve(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        let serial_no = ctx.param("serial", SERIAL_NO)?;
+        let (x, y, _) = find_largest_total_power(serial_no, 3, 3);
+        println!("Max 3x3 square: {:?}", (x, y, 3));
+        ctx.answer(1, format!("{},{}", x, y));
+        ctx.explain("part1", square_evidence(serial_no, x, y, 3));
+        let (x, y, size) = find_largest_total_power(serial_no, 1, 300);
+        println!("Max NxN square: {:?}", (x, y, size));
+        ctx.answer(2, format!("{},{},{}", x, y, size));
+        ctx.explain("part2", square_evidence(serial_no, x, y, size));
         Ok(())
     }
 }
 
+fn square_evidence(serial_no: i64, x: i64, y: i64, size: i64) -> Evidence {
+    Evidence::record()
+        .with("serial", serial_no)
+        .with("top_left", (x, y))
+        .with("size", size)
+        .with("total_power", square_power_level(serial_no, x, y, size))
+}
+
 // Power in the single cell specified
 fn cell_power_level(serial_no: i64, x: i64, y: i64) -> i64 {
     let rack_id = x + 10;
@@ -70,3 +89,37 @@ cached! {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::{cell_power_level, square_power_level};
+    use util::prop;
+
+    #[test]
+    fn cell_examples() {
+        assert_eq!(4, cell_power_level(8, 3, 5));
+        assert_eq!(-5, cell_power_level(57, 122, 79));
+        assert_eq!(0, cell_power_level(39, 217, 196));
+        assert_eq!(4, cell_power_level(71, 101, 153));
+    }
+
+    #[test]
+    fn square_power_is_the_sum_of_its_cells() {
+        prop::check("memoized square power matches adding up every cell",
+                    |rng, size| {
+                        let size = 1 + rng.below(size / 4 + 1) as i64;
+                        (rng.below(10_000) as i64,
+                         1 + rng.below(300 - size as usize + 1) as i64,
+                         1 + rng.below(300 - size as usize + 1) as i64,
+                         size)
+                    },
+                    |_| Vec::new(),
+                    |&(serial_no, x, y, size)| {
+                        square_power_level(serial_no, x, y, size) ==
+                            (x..x+size).flat_map(|cx| {
+                                (y..y+size).map(move |cy| {
+                                    cell_power_level(serial_no, cx, cy)
+                                })
+                            }).sum::<i64>()
+                    });
+    }
+}
@@ -1,11 +1,17 @@
-use advent::AdventSolver;
+use advent::{AdventSolver, Context};
 use failure::Error;
 use regex::Regex;
 use std::collections::{HashMap,VecDeque};
 use std::collections::hash_map::DefaultHasher;
 use std::fs::File;
 use std::hash::{Hash,Hasher};
-use std::io::{BufRead,BufReader};
+use std::io::Read;
+use std::path::Path;
+use std::str;
+use util::evidence::Evidence;
+
+// Give up looking for a cycle after this many generations.
+const MAX_GENERATIONS: u64 = 10_000;
 
 #[derive(PartialEq, Eq, Hash)]
 pub struct Solver {
@@ -22,8 +28,8 @@ pub struct Solver {
 }
 
 impl AdventSolver for Solver {
-    fn solve(&mut self) -> Result<(), Error> {
-        self.read_input()?;
+    fn solve(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        self.read_input(&ctx.input_path())?;
 
         // Map of hash value -> (generation, sum of plant positions).
         let mut seen_states: HashMap<u64, (u64, i64)> = HashMap::new();
@@ -34,32 +40,35 @@ impl AdventSolver for Solver {
                 self.get_hash(),
                 (self.generation, self.sum_of_plant_positions()));
         }
-        println!("After 20 generations: {}", self.sum_of_plant_positions());
+        let sum_after_20 = self.sum_of_plant_positions();
+        println!("After 20 generations: {}", sum_after_20);
+        ctx.answer(1, sum_after_20);
 
         // Took a while to discover this, but my input eventually reaches a
         // steady state, except that the pattern is migrating to the right.
         // Since it can only move linearly with respect to generations, we can
         // discover the delta between cycles. My cycle length was 1 so I'm not
         // handling longer cycles, which would be hard. :P
-        let delta;
-        loop {
-            self.spread();
-            let hash = self.get_hash();
-            let sum = self.sum_of_plant_positions();
-            if seen_states.contains_key(&hash) {
-                let (prev_gen, prev_sum) = seen_states[&hash];
-                println!("Cycle found from generation {} -> {}.",
-                         prev_gen, self.generation);
-                delta = self.sum_of_plant_positions() - prev_sum;
-                break;
-            } else {
-                seen_states.insert(hash, (self.generation, sum));
-            }
-        }
+        let (prev_gen, delta) =
+            self.find_cycle(&mut seen_states, MAX_GENERATIONS)?;
+        println!("Cycle found from generation {} -> {}.",
+                 prev_gen, self.generation);
 
-        let gens_remaining = 50_000_000_000 - self.generation as i64;
-        let result = self.sum_of_plant_positions() + delta * gens_remaining;
+        let result = self.extrapolate(50_000_000_000, delta)?;
         println!("After 50 billion generations: {}", result);
+        ctx.answer(2, result);
+
+        ctx.explain("part1", Evidence::record()
+            .with("generations", 20)
+            .with("sum_of_plant_positions", sum_after_20));
+        ctx.explain("part2", Evidence::record()
+            .with("cycle_start_generation", prev_gen)
+            .with("cycle_detected_at_generation", self.generation)
+            .with("sum_at_detection", self.sum_of_plant_positions())
+            .with("delta_per_generation", delta)
+            .with("state", self.state_string())
+            .with("generations", 50_000_000_000u64)
+            .with("sum_of_plant_positions", result));
         Ok(())
     }
 }
@@ -77,12 +86,22 @@ impl Default for Solver {
 }
 
 impl Solver {
-    fn read_input(&mut self) -> Result<(), Error> {
-        let lines = BufReader::new(File::open("input/day12.txt")?)
-                              .lines()
-                              .collect::<Result<Vec<String>, _>>()?;
-        self.parse_initial_state(&lines[0])?;
-        self.parse_rules(&lines[2..])?;
+    fn read_input(&mut self, path: &Path) -> Result<(), Error> {
+        let mut input = String::new();
+        File::open(path)?.read_to_string(&mut input)?;
+        self.parse_input(&input)
+    }
+
+    fn parse_input(&mut self, input: &str) -> Result<(), Error> {
+        let mut lines = input.lines();
+        match lines.next() {
+            Some(line) => self.parse_initial_state(line)?,
+            None => return Err(format_err!("Missing initial state")),
+        }
+        // A blank line separates the initial state from the rules.
+        let rules = lines.filter(|line| !line.is_empty())
+                         .collect::<Vec<&str>>();
+        self.parse_rules(&rules)?;
         self.pad();
         Ok(())
     }
@@ -102,8 +121,9 @@ impl Solver {
         }
     }
 
-    fn parse_rules(&mut self, lines: &[String]) -> Result<(), Error> {
-        let rule_re = Regex::new(r"^([.#]+) => ([.#])$")?;
+    fn parse_rules(&mut self, lines: &[&str]) -> Result<(), Error> {
+        // This assumes the rules are 5 bits long
+        let rule_re = Regex::new(r"^([.#]{5}) => ([.#])$")?;
         for line in lines {
             match rule_re.captures(line) {
                 Some(caps) => {
@@ -117,6 +137,11 @@ impl Solver {
                 }
             }
         }
+        // The state only tracks the span of pots around the plants, which
+        // can't work if every empty pot out to infinity sprouts a plant.
+        if self.rules[0] {
+            return Err(format_err!("Rule ..... => # grows infinitely"));
+        }
         Ok(())
     }
 
@@ -153,7 +178,9 @@ impl Solver {
     // Run one generation of rule simulation, mutating state appropriately.
     fn spread(&mut self) {
         let state_copy = self.state.clone();
-        for i in 2..state_copy.len()-2 {
+        // With no plants at all, pad() leaves the state unpadded (and likely
+        // too short to apply any rules), but nothing can grow anyway.
+        for i in 2..state_copy.len().saturating_sub(2) {
             self.state[i] = self.apply_rule(&state_copy, i);
         }
         self.pad();
@@ -166,6 +193,37 @@ impl Solver {
         self.rules[rule_index]
     }
 
+    // Keep spreading until a state repeats one in `seen_states`, recording
+    // new states as we go. Returns the generation the cycle started at and
+    // how much the sum of plant positions moves each generation.
+    fn find_cycle(&mut self, seen_states: &mut HashMap<u64, (u64, i64)>,
+                  max_generations: u64) -> Result<(u64, i64), Error> {
+        while self.generation < max_generations {
+            self.spread();
+            let hash = self.get_hash();
+            let sum = self.sum_of_plant_positions();
+            if seen_states.contains_key(&hash) {
+                let (prev_gen, prev_sum) = seen_states[&hash];
+                return Ok((prev_gen, sum - prev_sum));
+            } else {
+                seen_states.insert(hash, (self.generation, sum));
+            }
+        }
+        Err(format_err!("No cycle found within {} generations",
+                        max_generations))
+    }
+
+    // Project the sum of plant positions forward to a later generation,
+    // assuming it keeps changing by `delta` per generation.
+    fn extrapolate(&mut self, generation: u64, delta: i64)
+            -> Result<i64, Error> {
+        let gens_remaining = generation as i64 - self.generation as i64;
+        delta.checked_mul(gens_remaining)
+             .and_then(|d| d.checked_add(self.sum_of_plant_positions()))
+             .ok_or_else(|| format_err!("Sum overflows after {} generations",
+                                        generation))
+    }
+
     fn get_hash(&self) -> u64 {
         let mut hasher = DefaultHasher::new();
         self.state.hash(&mut hasher);
@@ -185,11 +243,130 @@ impl Solver {
                   .sum::<i64>()
     }
 
+    // Fuzz target: input parsing alone.
+    pub fn fuzz_parse(data: &[u8]) -> Result<(), Error> {
+        Solver::default().parse_input(str::from_utf8(data)?)
+    }
+
+    // Fuzz target: parsing plus both parts, with a much shorter leash on
+    // the cycle search.
+    pub fn fuzz_solve(data: &[u8]) -> Result<(), Error> {
+        let mut solver = Solver::default();
+        solver.parse_input(str::from_utf8(data)?)?;
+        let mut seen_states = HashMap::new();
+        while solver.generation < 20 {
+            solver.spread();
+            seen_states.insert(solver.get_hash(),
+                               (solver.generation,
+                                solver.sum_of_plant_positions()));
+        }
+        let (_, delta) = solver.find_cycle(&mut seen_states, 200)?;
+        solver.extrapolate(50_000_000_000, delta)?;
+        Ok(())
+    }
+
+    fn state_string(&self) -> String {
+        self.state.iter().map(|&v| if v { '#' } else { '.' }).collect()
+    }
+
     #[allow(dead_code)]
     fn dump(&self) {
-        println!("state: {}",
-                 self.state.iter().map(|&v| if v { '#' } else { '.' })
-                                  .collect::<String>());
+        println!("state: {}", self.state_string());
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::Solver;
+    use std::collections::BTreeSet;
+    use util::prop;
+    use util::rng::Rng;
+
+    // An initial state and a rule table that doesn't grow plants from
+    // nothing.
+    fn random_pots(rng: &mut Rng, size: usize) -> (String, Vec<bool>) {
+        let state = prop::string_from(rng, size, ".#");
+        let rules = (0..32).map(|i| i > 0 && rng.below(2) == 0).collect();
+        (format!(".{}", state), rules)
+    }
+
+    fn shrink_pots(&(ref state, ref rules): &(String, Vec<bool>))
+            -> Vec<(String, Vec<bool>)> {
+        let mut result: Vec<(String, Vec<bool>)> =
+            prop::shrink_string(state)
+                .into_iter()
+                .filter(|state| !state.is_empty())
+                .map(|state| (state, rules.clone()))
+                .collect();
+        for i in 0..rules.len() {
+            if rules[i] {
+                let mut fewer = rules.clone();
+                fewer[i] = false;
+                result.push((state.clone(), fewer));
+            }
+        }
+        result
+    }
+
+    fn input(state: &str, rules: &[bool]) -> String {
+        let pot = |plant| if plant { '#' } else { '.' };
+        let mut input = format!("initial state: {}\n\n", state);
+        for (i, &grows) in rules.iter().enumerate() {
+            let pattern: String = (0..5).rev()
+                                        .map(|bit| pot(i >> bit & 1 == 1))
+                                        .collect();
+            input += &format!("{} => {}\n", pattern, pot(grows));
+        }
+        input
+    }
+
+    // The plants as a set of pot numbers, grown one pot at a time.
+    fn simulate(state: &str, rules: &[bool], generations: usize)
+            -> BTreeSet<i64> {
+        let mut plants: BTreeSet<i64> =
+            state.chars()
+                 .enumerate()
+                 .filter(|&(_, c)| c == '#')
+                 .map(|(i, _)| i as i64)
+                 .collect();
+        for _ in 0..generations {
+            let (first, last) = match (plants.iter().next(),
+                                       plants.iter().next_back()) {
+                (Some(&first), Some(&last)) => (first, last),
+                _ => break,
+            };
+            plants = (first-2..=last+2)
+                .filter(|&pot| {
+                    let pattern = (pot-2..=pot+2)
+                        .fold(0, |acc, p| (acc << 1) +
+                                          plants.contains(&p) as usize);
+                    rules[pattern]
+                })
+                .collect();
+        }
+        plants
+    }
+
+    #[test]
+    fn spread_matches_a_set_of_pots() {
+        prop::check("spreading matches growing each pot from the rules",
+                    random_pots,
+                    shrink_pots,
+                    |&(ref state, ref rules)| {
+                        let mut solver = Solver::default();
+                        solver.parse_input(&input(state, rules)).unwrap();
+                        for _ in 0..10 {
+                            solver.spread();
+                        }
+                        let offset = solver.offset;
+                        let plants: BTreeSet<i64> =
+                            solver.state.iter()
+                                        .enumerate()
+                                        .filter(|&(_, &v)| v)
+                                        .map(|(i, _)| i as i64 + offset)
+                                        .collect();
+                        plants == simulate(state, rules, 10)
+                    });
+    }
+}
@@ -1,12 +1,17 @@
-use advent::AdventSolver;
+use advent::{AdventSolver, Context};
 use failure::Error;
 use num::FromPrimitive;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufRead,BufReader};
+use std::io::Read;
+use std::str;
+use util::evidence::Evidence;
 #[allow(unused_imports)]
 use std::{thread, time};
 
+// Give up on carts that never seem to crash into each other.
+const MAX_TICKS: usize = 1_000_000;
+
 #[derive(Default)]
 pub struct Solver;
 
@@ -50,6 +55,14 @@ struct Cart {
     next_turn: RelativeDirection,
 }
 
+// Two carts meeting at (x, y), partway through the given tick.
+struct Crash {
+    tick: usize,
+    x: usize,
+    y: usize,
+    carts: (usize, usize),
+}
+
 struct Map {
     map_data: Vec<MapCell>,
     width: usize,
@@ -67,15 +80,21 @@ impl Cart {
         }
     }
 
-    // Move the cart by 1 tick and possibly turn.
-    fn step(&mut self, map: &Map) {
+    // Move the cart by 1 tick and possibly turn. Fails if the cart leaves
+    // the track.
+    fn step(&mut self, map: &Map) -> Result<(), Error> {
         // We always leave the cart pointing in the direction it will move on
         // its next move, so obey direction naively here.
+        let (x, y) = (self.x, self.y);
         match self.dir {
-            Direction::Up =>    { self.y -= 1; },
-            Direction::Right => { self.x += 1; },
-            Direction::Down =>  { self.y += 1; },
-            Direction::Left =>  { self.x -= 1; },
+            Direction::Up =>    { self.y = y.wrapping_sub(1); },
+            Direction::Right => { self.x = x + 1; },
+            Direction::Down =>  { self.y = y + 1; },
+            Direction::Left =>  { self.x = x.wrapping_sub(1); },
+        }
+        if self.x >= map.width || self.y >= map.height {
+            return Err(format_err!("Cart {} fell off the map at ({}, {})",
+                                   self.id, x, y));
         }
 
         // Some locations will cause the cart to turn, do so now.
@@ -117,9 +136,11 @@ impl Cart {
                 self.turn();
             },
             MapCell::Empty => {
-                panic!();
+                return Err(format_err!("Cart {} derailed at ({}, {})",
+                                       self.id, self.x, self.y));
             }
         }
+        Ok(())
     }
 
     // Rotates the cart according to the rules, but does not move it.
@@ -134,9 +155,15 @@ impl Cart {
 }
 
 impl Map {
-    fn from_text(lines: &Vec<String>) -> Result<(Map, Vec<Cart>), Error> {
+    fn from_text(lines: &Vec<&str>) -> Result<(Map, Vec<Cart>), Error> {
         let height = lines.len();
-        let width = lines[0].len();
+        // Lines aren't necessarily padded out with spaces, so the widest one
+        // sets the width and the rest get filled in with empty cells.
+        let width = lines.iter().map(|line| line.chars().count()).max();
+        let width = match width {
+            Some(width) if width > 0 => width,
+            _ => return Err(format_err!("Empty map")),
+        };
         let mut map_data = Vec::new();
         let mut carts = Vec::new();
 
@@ -198,6 +225,9 @@ impl Map {
                     _ => return Err(format_err!("Invalid map char: {}", c))
                 });
             }
+            for _ in line.chars().count()..width {
+                map_data.push(MapCell::Empty);
+            }
         }
 
         let map = Map {
@@ -256,19 +286,66 @@ impl Map {
 }
 
 impl AdventSolver for Solver {
-    fn solve(&mut self) -> Result<(), Error> {
-        let lines = BufReader::new(File::open("input/day13.txt")?)
-                              .lines()
-                              .collect::<Result<Vec<String>, _>>()?;
-        let (map, mut carts) = Map::from_text(&lines)?;
+    fn solve(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        let mut input = String::new();
+        File::open(ctx.input_path())?.read_to_string(&mut input)?;
+        let lines = input.lines().collect::<Vec<&str>>();
+        let (map, carts) = Map::from_text(&lines)?;
+        let (crashes, last_cart) = Self::run_carts(&map, carts, MAX_TICKS)?;
+        for crash in crashes.iter() {
+            println!("Crash occurred at ({}, {})", crash.x, crash.y);
+        }
+        println!("Last cart: {:?}", last_cart);
+        // run_carts needs two carts to start with, and only succeeds once
+        // every cart but one has crashed, so there's always a first crash.
+        ctx.answer(1, format!("{},{}", crashes[0].x, crashes[0].y));
+        ctx.answer(2, format!("{},{}", last_cart.x, last_cart.y));
+
+        if ctx.explaining() {
+            let crash_list = crashes.iter().map(|crash| {
+                Evidence::record()
+                    .with("tick", crash.tick)
+                    .with("position", (crash.x, crash.y))
+                    .with("carts", crash.carts)
+            });
+            ctx.explain("crashes", Evidence::list(crash_list));
+            ctx.explain("last_cart", Evidence::record()
+                .with("id", last_cart.id)
+                .with("position", (last_cart.x, last_cart.y))
+                .with("direction", format!("{:?}", last_cart.dir)));
+        }
+        Ok(())
+    }
+}
+
+impl Solver {
+    // Run the carts until only one remains. Returns the crashes, in order,
+    // and the last cart standing.
+    fn run_carts(map: &Map, mut carts: Vec<Cart>, max_ticks: usize)
+            -> Result<(Vec<Crash>, Cart), Error> {
+        // A lone cart never crashes, so there'd be no part 1 answer.
+        if carts.len() < 2 {
+            return Err(format_err!("Need at least two carts, found {}",
+                                   carts.len()));
+        }
+        let mut crashes = Vec::new();
+        let mut ticks = 0;
         while carts.len() > 1 {
+            if ticks == max_ticks {
+                return Err(format_err!("{} carts still running after {} ticks",
+                                       carts.len(), max_ticks));
+            }
             carts.sort_by_key(|cart| (cart.y, cart.x));
             let mut i = 0;
             while i < carts.len() {
-                carts[i].step(&map);
+                carts[i].step(map)?;
                 if let Some(j) = Self::detect_collisions(&carts[i], &carts) {
-                    println!("Crash occurred at ({}, {})",
-                             carts[i].x, carts[i].y);
+                    crashes.push(Crash {
+                        tick: ticks,
+                        x: carts[i].x,
+                        y: carts[i].y,
+                        carts: (carts[i].id, j),
+                    });
                     carts.remove(i);
                     let r = Self::index_of(j, &carts);
                     carts.remove(r);
@@ -279,16 +356,32 @@ impl AdventSolver for Solver {
                     i += 1;
                 }
             }
+            ticks += 1;
             // Uncomment for cute animations
             //map.draw(&carts);
             //thread::sleep(time::Duration::from_millis(400));
         }
-        println!("Last cart: {:?}", carts[0]);
+        match carts.pop() {
+            Some(cart) => Ok((crashes, cart)),
+            None => Err(format_err!("Every cart crashed")),
+        }
+    }
+
+    // Fuzz target: map parsing alone.
+    pub fn fuzz_parse(data: &[u8]) -> Result<(), Error> {
+        let input = str::from_utf8(data)?;
+        Map::from_text(&input.lines().collect())?;
+        Ok(())
+    }
+
+    // Fuzz target: parsing plus the whole simulation, on a shorter clock.
+    pub fn fuzz_solve(data: &[u8]) -> Result<(), Error> {
+        let input = str::from_utf8(data)?;
+        let (map, carts) = Map::from_text(&input.lines().collect())?;
+        Self::run_carts(&map, carts, 1000)?;
         Ok(())
     }
-}
 
-impl Solver {
     // Returns the id of the cart that moving_cart collided with, or None if
     // there is no collision.
     fn detect_collisions(moving_cart: &Cart, carts: &Vec<Cart>)
@@ -314,3 +407,61 @@ impl Solver {
 fn modulo(num: i32, modulus: i32) -> i32 {
     ((num % modulus) + modulus) % modulus
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{modulo, Cart, Direction, Map, RelativeDirection, Solver};
+    use num::FromPrimitive;
+    use util::prop;
+
+    #[test]
+    fn turn_cycles_left_straight_right() {
+        prop::check("intersections go left, straight, right, repeat",
+                    |rng, size| (rng.below(4) as i32, rng.below(size + 1)),
+                    |&(dir, turns)| {
+                        prop::shrink_usize(&turns).into_iter()
+                                                  .map(|t| (dir, t))
+                                                  .collect()
+                    },
+                    |&(dir, turns)| {
+                        let start = Direction::from_i32(dir).unwrap();
+                        let mut cart = Cart::new(0, 0, 0, start);
+                        let mut expected = dir;
+                        for i in 0..turns {
+                            let next = [RelativeDirection::Left,
+                                        RelativeDirection::Straight,
+                                        RelativeDirection::Right][i % 3];
+                            if cart.next_turn != next {
+                                return false;
+                            }
+                            cart.turn();
+                            expected = modulo(expected + next as i32, 4);
+                            if cart.dir as i32 != expected {
+                                return false;
+                            }
+                        }
+                        // Every full cycle of turns ends up facing the same
+                        // way it started.
+                        turns % 3 != 0 || cart.dir == start
+                    });
+    }
+
+    fn run(lines: &[&str]) -> Result<String, String> {
+        let (map, carts) = Map::from_text(&lines.to_vec()).unwrap();
+        Solver::run_carts(&map, carts, 100)
+            .map(|(crashes, last)| {
+                format!("{},{} {},{}", crashes[0].x, crashes[0].y,
+                        last.x, last.y)
+            })
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn needs_two_carts() {
+        assert_eq!(Err("Need at least two carts, found 1".to_string()),
+                   run(&["/->-\\", "|   |", "\\---/"]));
+        assert_eq!(Err("Need at least two carts, found 0".to_string()),
+                   run(&["/---\\", "|   |", "\\---/"]));
+        assert_eq!(Ok("2,0 6,0".to_string()), run(&["->-<->--"]));
+    }
+}
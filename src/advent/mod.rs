@@ -1,8 +1,221 @@
 use failure::Error;
+use options::Options;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use util::evidence::Evidence;
+use util::fuzz::{self, Target};
 
 trait AdventSolver {
-    fn solve(&mut self) -> Result<(), Error>;
+    // Names of the parameters this day accepts through --param.
+    fn params(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    fn solve(&mut self, ctx: &mut Context) -> Result<(), Error>;
+}
+
+// Handed to a solver for each run, to say how it's being run and to collect
+// anything it reports along the way.
+pub struct Context {
+    day: usize,
+    input: Option<PathBuf>,
+    params: HashMap<String, String>,
+    explain: bool,
+    evidence: Vec<(String, Evidence)>,
+    answers: Vec<Answer>,
+    // When the current part started: the start of the run, or the time the
+    // previous answer came in.
+    mark: Instant,
+}
+
+// One part's answer, as a solver reported it.
+pub struct Answer {
+    pub part: usize,
+    pub answer: String,
+    pub elapsed: Duration,
+}
+
+impl Context {
+    pub fn new(day: usize, options: &Options) -> Context {
+        Context {
+            day: day,
+            input: options.inputs.get(&day).cloned(),
+            params: options.params.get(&day).cloned().unwrap_or_default(),
+            explain: options.explain,
+            evidence: Vec::new(),
+            answers: Vec::new(),
+            mark: Instant::now(),
+        }
+    }
+
+    // The input file for this day: input/dayNN.txt unless told otherwise.
+    pub fn input_path(&self) -> PathBuf {
+        match self.input {
+            Some(ref path) => path.clone(),
+            None => PathBuf::from(format!("input/day{:02}.txt", self.day)),
+        }
+    }
+
+    // The input, read a line at a time. An input path of "-" means stdin.
+    pub fn input_reader(&self) -> Result<Box<dyn BufRead>, Error> {
+        let path = self.input_path();
+        if path.to_str() == Some("-") {
+            Ok(Box::new(BufReader::new(io::stdin())))
+        } else {
+            Ok(Box::new(BufReader::new(File::open(path)?)))
+        }
+    }
+
+    // The value of a parameter, or `default` if it wasn't given.
+    pub fn param<T: FromStr>(&self, name: &str, default: T)
+            -> Result<T, Error> {
+        match self.params.get(name) {
+            Some(value) => value.parse::<T>().map_err(|_| {
+                format_err!("Bad value for parameter {}: {}", name, value)
+            }),
+            None => Ok(default),
+        }
+    }
+
+    fn check_params(&self, known: &[&str]) -> Result<(), Error> {
+        let mut names: Vec<&String> = self.params.keys().collect();
+        names.sort();
+        match names.iter().find(|name| !known.contains(&name.as_str())) {
+            Some(name) => Err(unknown_param(self.day, name, known)),
+            None => Ok(()),
+        }
+    }
+
+    // True when running with --explain. Solvers should check this before
+    // going to any trouble gathering evidence.
+    pub fn explaining(&self) -> bool {
+        self.explain
+    }
+
+    // Record evidence behind an answer. Ignored unless explaining.
+    pub fn explain<E: Into<Evidence>>(&mut self, key: &str, evidence: E) {
+        if self.explain {
+            self.evidence.push((key.to_string(), evidence.into()));
+        }
+    }
+
+    // Record the answer to a part, for the run history. Its time is counted
+    // from the previous answer, so part 1's includes reading the input.
+    pub fn answer<T: ToString>(&mut self, part: usize, answer: T) {
+        let now = Instant::now();
+        self.answers.push(Answer {
+            part: part,
+            answer: answer.to_string(),
+            elapsed: now - self.mark,
+        });
+        self.mark = now;
+    }
+
+    pub fn answers(&self) -> &[Answer] {
+        &self.answers
+    }
+
+    // Everything explained so far, or None if the solver had nothing to say.
+    pub fn evidence(&self) -> Option<Evidence> {
+        if self.evidence.is_empty() {
+            None
+        } else {
+            Some(Evidence::Record(self.evidence.clone()))
+        }
+    }
 }
 
 // Generated by build.rs
 include!("_all_days.rs");
+
+// Names of the parameters a day accepts.
+pub fn params(day: usize) -> Result<&'static [&'static str], Error> {
+    Ok(solver(day)?.params())
+}
+
+pub fn unknown_param(day: usize, name: &str, known: &[&str]) -> Error {
+    if known.is_empty() {
+        format_err!("Day {} doesn't take any parameters (got {})", day, name)
+    } else {
+        format_err!("Unknown parameter for day {}: {} (expected one of: {})",
+                    day, name, known.join(", "))
+    }
+}
+
+// Everything --fuzz knows how to exercise. The "parse" targets stop after
+// parsing, the "solve" targets go on to compute both answers.
+pub fn fuzz_targets() -> Vec<Target> {
+    vec![
+        Target { name: "day03-parse", seeds: &["input/day03.txt"],
+                 run: day03::Solver::fuzz_parse },
+        Target { name: "day03-solve", seeds: &["input/day03.txt"],
+                 run: day03::Solver::fuzz_solve },
+        Target { name: "day04-parse", seeds: &["input/day04.txt"],
+                 run: day04::Solver::fuzz_parse },
+        Target { name: "day04-solve", seeds: &["input/day04.txt"],
+                 run: day04::Solver::fuzz_solve },
+        Target { name: "day08-parse", seeds: &["input/day08.txt"],
+                 run: day08::Solver::fuzz_parse },
+        Target { name: "day08-solve", seeds: &["input/day08.txt"],
+                 run: day08::Solver::fuzz_solve },
+        Target { name: "day12-parse", seeds: &["input/day12.txt"],
+                 run: day12::Solver::fuzz_parse },
+        Target { name: "day12-solve", seeds: &["input/day12.txt"],
+                 run: day12::Solver::fuzz_solve },
+        Target { name: "day13-parse",
+                 seeds: &["input/day13.txt", "input/day13_ex.txt",
+                          "input/day13_ex2.txt"],
+                 run: day13::Solver::fuzz_parse },
+        Target { name: "day13-solve",
+                 seeds: &["input/day13.txt", "input/day13_ex.txt",
+                          "input/day13_ex2.txt"],
+                 run: day13::Solver::fuzz_solve },
+    ]
+}
+
+// Fuzz the named target (or "all" of them), failing if anything panicked.
+pub fn fuzz(name: &str, runs: usize, seed: u64) -> Result<(), Error> {
+    let targets: Vec<Target> = fuzz_targets().into_iter()
+                                             .filter(|t| {
+                                                 name == "all" || t.name == name
+                                             })
+                                             .collect();
+    if targets.is_empty() {
+        return Err(format_err!("Unknown fuzz target: {}", name));
+    }
+    let mut panics = 0;
+    for target in targets {
+        let stats = fuzz::run(target, runs, seed,
+                              Path::new(fuzz::CORPUS_DIR))?;
+        println!("{}: {} runs, {} errors, {} panics",
+                 target.name, stats.runs, stats.errors, stats.panics);
+        panics += stats.panics;
+    }
+    if panics > 0 {
+        return Err(format_err!("{} inputs caused panics", panics));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fuzz_targets;
+    use std::env;
+    use util::fuzz;
+
+    // A short campaign against every target. The saved corpus is replayed
+    // first, so this also keeps old crashes from coming back. Any new ones
+    // go to a temporary directory rather than into the source tree.
+    #[test]
+    fn fuzz_targets_do_not_panic() {
+        let crash_dir = env::temp_dir().join("advent2018-fuzz");
+        for target in fuzz_targets() {
+            let stats = fuzz::run(target, 100, 2018, &crash_dir).unwrap();
+            assert_eq!(0, stats.panics, "{} panicked", target.name);
+        }
+    }
+}
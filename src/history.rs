@@ -0,0 +1,393 @@
+// The run history: every answer a run comes up with is appended to a file as
+// one line of JSON, so we can look back at how answers and timings changed,
+// and catch the same input giving a different answer than it used to.
+use advent::Answer;
+use failure::Error;
+use std::char::from_u32;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use util::config::Value;
+use util::evidence::Evidence;
+
+// One answer from one run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Run {
+    // Seconds since the Unix epoch.
+    pub time: u64,
+    pub day: usize,
+    pub part: usize,
+    pub answer: String,
+    // None if the input couldn't be hashed (it came from stdin).
+    pub input_hash: Option<String>,
+    pub elapsed_us: u64,
+    pub commit: Option<String>,
+}
+
+impl Run {
+    fn to_json(&self) -> String {
+        let mut record = Evidence::record()
+            .with("time", self.time)
+            .with("day", self.day)
+            .with("part", self.part)
+            .with("answer", self.answer.as_str());
+        if let Some(ref input_hash) = self.input_hash {
+            record = record.with("input_hash", input_hash.as_str());
+        }
+        record = record.with("elapsed_us", self.elapsed_us);
+        if let Some(ref commit) = self.commit {
+            record = record.with("commit", commit.as_str());
+        }
+        record.to_json()
+    }
+
+    fn from_json(line: &str) -> Result<Run, Error> {
+        let fields = parse_object(line)?;
+        let int = |key: &str| match fields.get(key) {
+            Some(&Value::Int(n)) if n >= 0 => Ok(n as u64),
+            _ => Err(format_err!("missing or bad {}", key)),
+        };
+        let string = |key: &str| match fields.get(key) {
+            Some(&Value::Str(ref s)) => Ok(s.clone()),
+            _ => Err(format_err!("missing or bad {}", key)),
+        };
+        Ok(Run {
+            time: int("time")?,
+            day: int("day")? as usize,
+            part: int("part")? as usize,
+            answer: string("answer")?,
+            input_hash: string("input_hash").ok(),
+            elapsed_us: int("elapsed_us")?,
+            commit: string("commit").ok(),
+        })
+    }
+}
+
+// Append a run's answers to the history file.
+pub fn record(path: &Path, day: usize, input_hash: Option<&str>,
+              answers: &[Answer]) -> Result<(), Error> {
+    if answers.is_empty() {
+        return Ok(());
+    }
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let commit = git_commit();
+    let mut lines = String::new();
+    for answer in answers {
+        let elapsed = answer.elapsed;
+        let run = Run {
+            time: time,
+            day: day,
+            part: answer.part,
+            answer: answer.answer.clone(),
+            input_hash: input_hash.map(|hash| hash.to_string()),
+            elapsed_us: elapsed.as_secs() * 1_000_000 +
+                        elapsed.subsec_micros() as u64,
+            commit: commit.clone(),
+        };
+        lines.push_str(&run.to_json());
+        lines.push('\n');
+    }
+    // One write, so runs going on at the same time can't interleave lines.
+    OpenOptions::new().create(true).append(true).open(path)?
+                      .write_all(lines.as_bytes())?;
+    Ok(())
+}
+
+// Everything in the history file, oldest first. Lines that can't be read
+// (say, from a run that was killed mid-write) are skipped with a warning.
+pub fn load(path: &Path) -> Result<Vec<Run>, Error> {
+    let text = fs::read_to_string(path).map_err(|e| {
+        format_err!("{}: {}", path.display(), e)
+    })?;
+    let mut runs = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match Run::from_json(line) {
+            Ok(run) => runs.push(run),
+            Err(e) => eprintln!("warning: {}: line {}: {}",
+                                path.display(), i + 1, e),
+        }
+    }
+    Ok(runs)
+}
+
+// Identifies what a day was asked: its input file (if there is one) and any
+// parameters, since either can change the answer. FNV-1a, because it needs to
+// stay the same from one build to the next. Input from stdin is gone by the
+// time the answers are in, so there's no telling those runs apart: None.
+pub fn input_hash(input: &Path, params: Option<&HashMap<String, String>>)
+        -> Option<String> {
+    if input.to_str() == Some("-") {
+        return None;
+    }
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut add = |bytes: &[u8]| {
+        for &b in bytes {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+    if let Ok(contents) = fs::read(input) {
+        add(&contents);
+    }
+    if let Some(params) = params {
+        let mut params: Vec<_> = params.iter().collect();
+        params.sort();
+        for (key, value) in params {
+            add(b"\n");
+            add(key.as_bytes());
+            add(b"=");
+            add(value.as_bytes());
+        }
+    }
+    Some(format!("{:016x}", hash))
+}
+
+// The commit being run, with "-dirty" if there are uncommitted changes. None
+// if git isn't around or this isn't a checkout.
+fn git_commit() -> Option<String> {
+    let output = Command::new("git").args(["rev-parse", "--short", "HEAD"])
+                                    .output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let mut commit = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    let clean = Command::new("git").args(["diff", "--quiet", "HEAD"])
+                                   .status().ok()?;
+    if !clean.success() {
+        commit.push_str("-dirty");
+    }
+    Some(commit)
+}
+
+// Print each part's runs for the given day (or every day), marking answers
+// that changed for the same input.
+pub fn show(path: &Path, day: Option<usize>) -> Result<(), Error> {
+    let runs: Vec<Run> = load(path)?.into_iter()
+                                    .filter(|run| day.is_none_or(|d| {
+                                        run.day == d
+                                    }))
+                                    .collect();
+    if runs.is_empty() {
+        match day {
+            Some(day) => println!("No runs recorded for day {}.", day),
+            None => println!("No runs recorded."),
+        }
+        return Ok(());
+    }
+    let mut parts: Vec<(usize, usize)> =
+        runs.iter().map(|run| (run.day, run.part)).collect();
+    parts.sort();
+    parts.dedup();
+    for (day, part) in parts {
+        let runs: Vec<&Run> = runs.iter()
+                                  .filter(|r| r.day == day && r.part == part)
+                                  .collect();
+        println!("Day {}, part {}: {} run{}", day, part, runs.len(),
+                 if runs.len() == 1 { "" } else { "s" });
+        for (run, changed) in runs.iter().zip(changed_answers(&runs)) {
+            println!("  {}  {:<14} input {}  {:>10}  {}{}",
+                     format_time(run.time),
+                     run.commit.as_ref().map_or("-", |c| c.as_str()),
+                     run.input_hash.as_ref().map_or("-", |hash| {
+                         &hash[..8.min(hash.len())]
+                     }),
+                     format_elapsed(run.elapsed_us),
+                     run.answer,
+                     match changed {
+                         Some(ref was) => format!("  <- CHANGED (was {})", was),
+                         None => String::new(),
+                     });
+        }
+        let fastest = runs.iter().map(|r| r.elapsed_us).min().unwrap();
+        let slowest = runs.iter().map(|r| r.elapsed_us).max().unwrap();
+        let latest = runs.last().unwrap().elapsed_us;
+        println!("  time: fastest {}, slowest {}, latest {}",
+                 format_elapsed(fastest), format_elapsed(slowest),
+                 format_elapsed(latest));
+    }
+    Ok(())
+}
+
+// For each run, the previous answer for the same input if this one differs
+// from it. That's a regression (or a fix) somewhere. Runs on an unknown input
+// aren't compared with anything.
+fn changed_answers(runs: &[&Run]) -> Vec<Option<String>> {
+    let mut last_answer: HashMap<&str, &str> = HashMap::new();
+    runs.iter()
+        .map(|run| {
+            let previous = run.input_hash.as_ref().and_then(|hash| {
+                last_answer.insert(hash, &run.answer)
+            });
+            match previous {
+                Some(previous) if previous != run.answer => {
+                    Some(previous.to_string())
+                },
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn format_elapsed(us: u64) -> String {
+    if us >= 1_000_000 {
+        format!("{:.2}s", us as f64 / 1_000_000.0)
+    } else {
+        format!("{:.1}ms", us as f64 / 1000.0)
+    }
+}
+
+// "YYYY-MM-DD HH:MM", in UTC.
+fn format_time(secs: u64) -> String {
+    // Days to a civil date, from Howard Hinnant's chrono-compatible
+    // algorithms (an era is 400 years, starting March 1st). Never negative,
+    // since times are unsigned.
+    let days = secs / 86400 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+                       - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4
+                                    - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    let minutes = secs % 86400 / 60;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day,
+            minutes / 60, minutes % 60)
+}
+
+// Reads the flat objects this module writes: string and integer values only.
+fn parse_object(text: &str) -> Result<HashMap<String, Value>, Error> {
+    let mut chars = text.trim().chars().peekable();
+    let mut fields = HashMap::new();
+    if chars.next() != Some('{') {
+        return Err(format_err!("expected {{"));
+    }
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            if chars.next() != Some('"') {
+                return Err(format_err!("expected a key"));
+            }
+            let key = parse_string(&mut chars)?;
+            if chars.next() != Some(':') {
+                return Err(format_err!("expected : after {}", key));
+            }
+            let value = if chars.peek() == Some(&'"') {
+                chars.next();
+                Value::Str(parse_string(&mut chars)?)
+            } else {
+                let mut digits = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_digit() && c != '-' {
+                        break;
+                    }
+                    digits.push(c);
+                    chars.next();
+                }
+                Value::Int(digits.parse::<i64>().map_err(|_| {
+                    format_err!("bad value for {}", key)
+                })?)
+            };
+            fields.insert(key, value);
+            match chars.next() {
+                Some(',') => {},
+                Some('}') => break,
+                _ => return Err(format_err!("expected , or }}")),
+            }
+        }
+    }
+    if chars.next().is_some() {
+        return Err(format_err!("junk after }}"));
+    }
+    Ok(fields)
+}
+
+// The rest of a string, after its opening quote.
+fn parse_string<I: Iterator<Item=char>>(chars: &mut I)
+        -> Result<String, Error> {
+    let mut result = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(result),
+            Some('\\') => match chars.next() {
+                Some('"') => result.push('"'),
+                Some('\\') => result.push('\\'),
+                Some('n') => result.push('\n'),
+                Some('u') => {
+                    let hex: String = chars.take(4).collect();
+                    let c = u32::from_str_radix(&hex, 16).ok()
+                                                         .and_then(from_u32);
+                    result.push(c.ok_or_else(|| format_err!("bad escape"))?);
+                },
+                _ => return Err(format_err!("bad escape")),
+            },
+            Some(c) => result.push(c),
+            None => return Err(format_err!("unterminated string")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{changed_answers, format_time, input_hash, Run};
+    use std::path::Path;
+
+    fn run(answer: &str, input_hash: &str) -> Run {
+        Run {
+            time: 1545696000,
+            day: 7,
+            part: 1,
+            answer: answer.to_string(),
+            input_hash: Some(input_hash.to_string()),
+            elapsed_us: 1234,
+            commit: Some("8ddf7e0".to_string()),
+        }
+    }
+
+    #[test]
+    fn runs_survive_a_round_trip() {
+        let mut original = run("say \"hi\"\n\u{1}", "00ff");
+        assert_eq!(original, Run::from_json(&original.to_json()).unwrap());
+        original.commit = None;
+        assert_eq!(original, Run::from_json(&original.to_json()).unwrap());
+        original.input_hash = None;
+        assert_eq!(original, Run::from_json(&original.to_json()).unwrap());
+        assert!(Run::from_json(r#"{"time":1,"day":7"#).is_err());
+        assert!(Run::from_json(r#"{"time":1}"#).is_err());
+    }
+
+    #[test]
+    fn changed_answer_for_same_input_is_flagged() {
+        let runs = [run("ABC", "1"), run("XYZ", "2"), run("ABC", "1"),
+                    run("ACB", "1"), run("XYZ", "2")];
+        let runs: Vec<&Run> = runs.iter().collect();
+        assert_eq!(vec![None, None, None, Some("ABC".to_string()), None],
+                   changed_answers(&runs));
+    }
+
+    #[test]
+    fn stdin_runs_are_never_flagged() {
+        assert_eq!(None, input_hash(Path::new("-"), None));
+        let mut first = run("ABC", "");
+        first.input_hash = None;
+        let mut second = run("XYZ", "");
+        second.input_hash = None;
+        assert_eq!(vec![None, None], changed_answers(&[&first, &second]));
+    }
+
+    #[test]
+    fn times_are_utc_dates() {
+        assert_eq!("1970-01-01 00:00", format_time(0));
+        assert_eq!("2018-12-25 00:00", format_time(1545696000));
+        assert_eq!("2000-02-29 23:59", format_time(951868740));
+    }
+}
@@ -6,28 +6,110 @@ extern crate image;
 extern crate itertools;
 #[macro_use] extern crate lazy_static;
 extern crate num;
-extern crate rand;
 extern crate regex;
 
 #[macro_use]
 mod util;
 mod advent;
+mod history;
+mod options;
 
-use argparse::{ArgumentParser, StoreOption};
+use argparse::{ArgumentParser, Collect, Store, StoreConst, StoreOption};
+use failure::Error;
+use options::Options;
+use std::path::PathBuf;
 
 fn main() {
+    let mut command: Option<String> = None;
     let mut day: Option<usize> = None;
+    let mut explain: Option<bool> = None;
+    let mut format: Option<String> = None;
+    let mut config: Option<PathBuf> = None;
+    let mut input: Option<PathBuf> = None;
+    let mut params: Vec<String> = Vec::new();
+    let mut fuzz_target: Option<String> = None;
+    let mut fuzz_runs: usize = 10_000;
+    let mut fuzz_seed: u64 = 1;
     {
         let mut parser = ArgumentParser::new();
         parser.set_description("Advent of Code 2018");
+        parser.refer(&mut command)
+              .add_argument("command", StoreOption,
+                            "\"history\" to show past runs (with --day, \
+                             just that day's)");
         parser.refer(&mut day)
               .add_option(&["-d", "--day"], StoreOption,
                           "number of challenge to run");
+        parser.refer(&mut explain)
+              .add_option(&["--explain"], StoreConst(Some(true)),
+                          "show the evidence behind the answers")
+              .add_option(&["--no-explain"], StoreConst(Some(false)),
+                          "don't, even if the config file says to");
+        parser.refer(&mut format)
+              .add_option(&["--format"], StoreOption,
+                          "format of --explain output: text or json");
+        parser.refer(&mut config)
+              .add_option(&["--config"], StoreOption,
+                          "config file (default: advent.toml)");
+        parser.refer(&mut input)
+              .add_option(&["--input"], StoreOption,
+                          "input file (default: input/dayNN.txt)");
+        parser.refer(&mut params)
+              .add_option(&["--param"], Collect,
+                          "puzzle parameter as key=value (repeatable)");
+        parser.refer(&mut fuzz_target)
+              .add_option(&["--fuzz"], StoreOption,
+                          "fuzz an input parser (e.g. day13-parse, or all)");
+        parser.refer(&mut fuzz_runs)
+              .add_option(&["--runs"], Store,
+                          "number of mutated inputs per fuzz target");
+        parser.refer(&mut fuzz_seed)
+              .add_option(&["--seed"], Store,
+                          "random seed for fuzzing");
         parser.parse_args_or_exit();
     }
+    if let Some(ref target) = fuzz_target {
+        match advent::fuzz(target, fuzz_runs, fuzz_seed) {
+            Ok(_) => {},
+            Err(e) => println!("error: {}", e)
+        }
+        return;
+    }
+    let config = config.as_deref();
+    if let Some(ref command) = command {
+        let result = match command.as_str() {
+            "history" => Options::load(config).and_then(|options| {
+                match options.history {
+                    Some(ref path) => history::show(path, day),
+                    None => Err(format_err!("History is turned off")),
+                }
+            }),
+            _ => Err(format_err!("Unknown command: {}", command)),
+        };
+        if let Err(e) = result {
+            println!("error: {}", e);
+        }
+        return;
+    }
     match day {
         Some(ref day) => {
-            match advent::solve(*day) {
+            let options = Options::load(config)
+                .and_then(|mut options| {
+                    if let Some(explain) = explain {
+                        options.explain = explain;
+                    }
+                    if let Some(format) = format {
+                        options.format = format;
+                    }
+                    if let Some(input) = input {
+                        options.inputs.insert(*day, input);
+                    }
+                    for param in params.iter() {
+                        options.parse_param(*day, param)?;
+                    }
+                    Ok(options)
+                });
+            match options.and_then(|options| run(*day, &options)) {
                 Ok(_) => {},
                 Err(e) => println!("error: {}", e)
             }
@@ -35,3 +117,37 @@ fn main() {
         None => println!("--day is required"),
     }
 }
+
+fn run(day: usize, options: &Options) -> Result<(), Error> {
+    let format = options.format.as_str();
+    if format != "text" && format != "json" {
+        return Err(format_err!("Unknown format: {}", format));
+    }
+    let mut ctx = advent::Context::new(day, options);
+    advent::solve(day, &mut ctx)?;
+    if let Some(ref path) = options.history {
+        // Losing the history isn't worth losing the answers over.
+        let input_hash = history::input_hash(&ctx.input_path(),
+                                             options.params.get(&day));
+        if let Err(e) = history::record(path, day, input_hash.as_deref(),
+                                        ctx.answers()) {
+            eprintln!("warning: couldn't record history in {}: {}",
+                      path.display(), e);
+        }
+    }
+    if options.explain {
+        // JSON goes on a single line, so it's easy to pick out of the rest of
+        // the output.
+        match ctx.evidence() {
+            Some(evidence) if format == "json" => {
+                println!("{}", evidence.to_json());
+            },
+            Some(evidence) => {
+                println!("\nExplanation:");
+                print!("{}", evidence.to_text());
+            },
+            None => println!("No explanation available for day {}.", day),
+        }
+    }
+    Ok(())
+}
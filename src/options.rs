@@ -0,0 +1,211 @@
+// Options for a run, from the config file (advent.toml) and then the command
+// line, which wins wherever both say something.
+use advent;
+use failure::Error;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use util::config::{self, Table, Value};
+
+#[derive(Debug)]
+pub struct Options {
+    pub explain: bool,
+    pub format: String,
+    // Where to record answers, if anywhere.
+    pub history: Option<PathBuf>,
+    pub inputs: HashMap<usize, PathBuf>,
+    pub params: HashMap<usize, HashMap<String, String>>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            explain: false,
+            format: "text".to_string(),
+            history: Some(PathBuf::from("advent-history.jsonl")),
+            inputs: HashMap::new(),
+            params: HashMap::new(),
+        }
+    }
+}
+
+impl Options {
+    // Options from the given config file, or from the first advent.toml found
+    // in the usual places, or the defaults if there isn't one.
+    pub fn load(path: Option<&Path>) -> Result<Options, Error> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match find_config() {
+                Some(path) => path,
+                None => return Ok(Options::default()),
+            },
+        };
+        let text = fs::read_to_string(&path).map_err(|e| {
+            format_err!("{}: {}", path.display(), e)
+        })?;
+        Self::from_config(&text).map_err(|e| {
+            format_err!("{}: {}", path.display(), e)
+        })
+    }
+
+    fn from_config(text: &str) -> Result<Options, Error> {
+        let mut options = Options::default();
+        for table in config::parse(text)? {
+            if table.name.is_empty() {
+                options.set_globals(&table)?;
+                continue;
+            }
+            // A day without a solver is as much a typo as a bad key.
+            let mut parts = table.name.splitn(2, '.');
+            let day = parse_day(parts.next().unwrap())
+                .filter(|&day| advent::params(day).is_ok())
+                .ok_or_else(|| format_err!("line {}: unknown table [{}]",
+                                           table.line, table.name))?;
+            match parts.next() {
+                None => options.set_day(day, &table)?,
+                Some("params") => options.set_params(day, &table)?,
+                Some(_) => return Err(format_err!("line {}: unknown table [{}]",
+                                                  table.line, table.name)),
+            }
+        }
+        Ok(options)
+    }
+
+    fn set_globals(&mut self, table: &Table) -> Result<(), Error> {
+        for entry in table.entries.iter() {
+            match (entry.key.as_str(), &entry.value) {
+                ("explain", &Value::Bool(b)) => self.explain = b,
+                ("format", &Value::Str(ref s)) => self.format = s.clone(),
+                ("history", &Value::Str(ref s)) => {
+                    self.history = Some(PathBuf::from(s));
+                },
+                ("history", &Value::Bool(false)) => self.history = None,
+                ("explain", _) | ("format", _) | ("history", _) => {
+                    return Err(format_err!("line {}: wrong type for {}",
+                                           entry.line, entry.key));
+                },
+                _ => return Err(format_err!("line {}: unknown key: {}",
+                                            entry.line, entry.key)),
+            }
+        }
+        Ok(())
+    }
+
+    fn set_day(&mut self, day: usize, table: &Table) -> Result<(), Error> {
+        for entry in table.entries.iter() {
+            match (entry.key.as_str(), &entry.value) {
+                ("input", &Value::Str(ref s)) => {
+                    self.inputs.insert(day, PathBuf::from(s));
+                },
+                ("input", _) => {
+                    return Err(format_err!("line {}: wrong type for input",
+                                           entry.line));
+                },
+                _ => return Err(format_err!("line {}: unknown key: {}",
+                                            entry.line, entry.key)),
+            }
+        }
+        Ok(())
+    }
+
+    fn set_params(&mut self, day: usize, table: &Table) -> Result<(), Error> {
+        let known = advent::params(day).map_err(|e| {
+            format_err!("line {}: {}", table.line, e)
+        })?;
+        for entry in table.entries.iter() {
+            if !known.contains(&entry.key.as_str()) {
+                return Err(format_err!("line {}: {}", entry.line,
+                                       advent::unknown_param(day, &entry.key,
+                                                             known)));
+            }
+            self.set_param(day, &entry.key, &entry.value.to_string());
+        }
+        Ok(())
+    }
+
+    pub fn set_param(&mut self, day: usize, key: &str, value: &str) {
+        self.params.entry(day)
+                   .or_insert_with(HashMap::new)
+                   .insert(key.to_string(), value.to_string());
+    }
+
+    // A --param flag, as key=value.
+    pub fn parse_param(&mut self, day: usize, param: &str)
+            -> Result<(), Error> {
+        match param.find('=') {
+            Some(pos) if pos > 0 => {
+                self.set_param(day, &param[..pos], &param[pos+1..]);
+                Ok(())
+            },
+            _ => Err(format_err!("Expected --param key=value, got: {}", param)),
+        }
+    }
+}
+
+// "day07" => 7
+fn parse_day(name: &str) -> Option<usize> {
+    name.strip_prefix("day")?.parse::<usize>().ok()
+}
+
+// ./advent.toml, or advent2018/advent.toml in the user's config directory.
+fn find_config() -> Option<PathBuf> {
+    let local = PathBuf::from("advent.toml");
+    if local.is_file() {
+        return Some(local);
+    }
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    let path = config_home.join("advent2018").join("advent.toml");
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Options;
+    use std::path::PathBuf;
+
+    #[test]
+    fn config_sections() {
+        let options = Options::from_config(r#"
+            format = "json"
+            [day07]
+            input = "example.txt"
+            [day07.params]
+            workers = 2
+        "#).unwrap();
+        assert_eq!("json", options.format);
+        assert!(!options.explain);
+        assert!(options.history.is_some());
+        assert!(Options::from_config("history = false").unwrap()
+                        .history.is_none());
+        assert_eq!(Some(&PathBuf::from("example.txt")), options.inputs.get(&7));
+        assert_eq!("2", options.params[&7]["workers"]);
+    }
+
+    #[test]
+    fn config_rejects_unknown_keys() {
+        let error = |text| Options::from_config(text).unwrap_err().to_string();
+        assert_eq!("line 1: unknown key: colour", error("colour = \"red\""));
+        assert_eq!("line 2: unknown key: inptu",
+                   error("[day01]\ninptu = \"x\""));
+        assert_eq!("line 1: unknown table [days]", error("[days]"));
+        assert_eq!("line 1: unknown table [day26]",
+                   error("[day26]\ninput = \"x\""));
+        assert_eq!("line 1: unknown table [day99.params]",
+                   error("[day99.params]"));
+        assert_eq!("line 1: wrong type for explain", error("explain = 1"));
+        assert_eq!("line 2: Unknown parameter for day 7: elves \
+                    (expected one of: workers, step_overhead, \
+                    default_duration)",
+                   error("[day07.params]\nelves = 3"));
+        assert_eq!("line 2: Day 8 doesn't take any parameters (got x)",
+                   error("[day08.params]\nx = 3"));
+    }
+}
@@ -0,0 +1,188 @@
+// Reader for the config file, which is a small subset of TOML: [table]
+// headers, `key = value` lines with string, integer or boolean values, and
+// # comments. That's all the config needs, and it isn't worth a dependency.
+use failure::Error;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Str(ref s) => write!(f, "{}", s),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Entry {
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+#[derive(Debug)]
+pub struct Table {
+    // Empty for the keys before the first [table] header.
+    pub name: String,
+    pub line: usize,
+    pub entries: Vec<Entry>,
+}
+
+// Errors carry 1-based line numbers.
+pub fn parse(text: &str) -> Result<Vec<Table>, Error> {
+    let mut tables = vec![Table { name: String::new(), line: 0,
+                                  entries: Vec::new() }];
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            if !line.ends_with(']') {
+                return Err(format_err!("line {}: unclosed table header",
+                                       line_no));
+            }
+            let name = line[1..line.len()-1].trim();
+            if !is_valid_name(name) {
+                return Err(format_err!("line {}: bad table name: {}",
+                                       line_no, name));
+            }
+            if tables.iter().any(|t| t.name == name) {
+                return Err(format_err!("line {}: duplicate table [{}]",
+                                       line_no, name));
+            }
+            tables.push(Table { name: name.to_string(), line: line_no,
+                                entries: Vec::new() });
+            continue;
+        }
+        let (key, value) = match line.find('=') {
+            Some(pos) => (line[..pos].trim(), line[pos+1..].trim()),
+            None => return Err(format_err!("line {}: expected key = value",
+                                           line_no)),
+        };
+        if !is_valid_name(key) || key.contains('.') {
+            return Err(format_err!("line {}: bad key: {}", line_no, key));
+        }
+        let value = parse_value(value)
+            .map_err(|e| format_err!("line {}: {}", line_no, e))?;
+        let table = tables.last_mut().unwrap();
+        if table.entries.iter().any(|e| e.key == key) {
+            return Err(format_err!("line {}: duplicate key: {}",
+                                   line_no, key));
+        }
+        table.entries.push(Entry { key: key.to_string(), value: value,
+                                   line: line_no });
+    }
+    Ok(tables)
+}
+
+// Table names and keys are bare words, with dots separating table names.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() &&
+    name.split('.').all(|part| {
+        !part.is_empty() &&
+        part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    })
+}
+
+// Drop a trailing comment, minding that strings can contain '#'.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\\' if in_string && !escaped => { escaped = true; continue; },
+            '"' if !escaped => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {},
+        }
+        escaped = false;
+    }
+    line
+}
+
+fn parse_value(text: &str) -> Result<Value, Error> {
+    if text.starts_with('"') {
+        parse_string(text)
+    } else if text == "true" || text == "false" {
+        Ok(Value::Bool(text == "true"))
+    } else {
+        text.replace('_', "")
+            .parse::<i64>()
+            .map(Value::Int)
+            .map_err(|_| format_err!("bad value: {}", text))
+    }
+}
+
+fn parse_string(text: &str) -> Result<Value, Error> {
+    let mut result = String::new();
+    let mut chars = text[1..].chars();
+    loop {
+        match chars.next() {
+            Some('"') => break,
+            Some('\\') => {
+                result.push(match chars.next() {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    _ => return Err(format_err!("bad escape in: {}", text)),
+                });
+            },
+            Some(c) => result.push(c),
+            None => return Err(format_err!("unterminated string: {}", text)),
+        }
+    }
+    if chars.next().is_some() {
+        return Err(format_err!("junk after string: {}", text));
+    }
+    Ok(Value::Str(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Value};
+
+    #[test]
+    fn parse_tables_and_values() {
+        let tables = parse(r#"
+            # Defaults for every day
+            explain = true
+
+            [day07]
+            input = "input/day07 #2.txt"  # not a comment in the string
+            [day07.params]
+            workers = 2
+            step_overhead = 1_000
+        "#).unwrap();
+        assert_eq!(3, tables.len());
+        assert_eq!("", tables[0].name);
+        assert_eq!(Value::Bool(true), tables[0].entries[0].value);
+        assert_eq!("day07", tables[1].name);
+        assert_eq!(Value::Str("input/day07 #2.txt".to_string()),
+                   tables[1].entries[0].value);
+        assert_eq!("day07.params", tables[2].name);
+        assert_eq!(Value::Int(1000), tables[2].entries[1].value);
+        assert_eq!(9, tables[2].entries[1].line);
+    }
+
+    #[test]
+    fn parse_errors_have_line_numbers() {
+        let error = |text| parse(text).unwrap_err().to_string();
+        assert_eq!("line 2: bad value: nope", error("\nx = nope"));
+        assert_eq!("line 1: unterminated string: \"abc",
+                   error("x = \"abc"));
+        assert_eq!("line 3: duplicate key: x", error("x = 1\n\nx = 2"));
+        assert_eq!("line 2: duplicate table [a]", error("[a]\n[a]"));
+        assert_eq!("line 1: expected key = value", error("just words"));
+    }
+}
@@ -0,0 +1,248 @@
+// Structured evidence behind an answer, for --explain. It's a small tree of
+// numbers, strings, lists and records, which can be printed for a human or
+// as JSON for a script.
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Evidence {
+    Int(i64),
+    // Counts too big for an Int.
+    UInt(u128),
+    Str(String),
+    List(Vec<Evidence>),
+    // Fields keep the order they were added in.
+    Record(Vec<(String, Evidence)>),
+}
+
+impl Evidence {
+    pub fn record() -> Evidence {
+        Evidence::Record(Vec::new())
+    }
+
+    // Add a field to a record, builder style.
+    pub fn with<E: Into<Evidence>>(mut self, key: &str, value: E) -> Evidence {
+        match self {
+            Evidence::Record(ref mut fields) => {
+                fields.push((key.to_string(), value.into()));
+            },
+            _ => panic!("Can only add fields to a record"),
+        }
+        self
+    }
+
+    pub fn list<E: Into<Evidence>, I: IntoIterator<Item=E>>(items: I)
+            -> Evidence {
+        Evidence::List(items.into_iter().map(|item| item.into()).collect())
+    }
+
+    // Indented, YAML-ish text. Lists of plain values stay on one line.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        if self.is_inline() {
+            out.push_str(&self.inline_text());
+            out.push('\n');
+        } else {
+            self.write_text(&mut out, 0);
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        match *self {
+            Evidence::Int(n) => n.to_string(),
+            Evidence::UInt(n) => n.to_string(),
+            Evidence::Str(ref s) => json_string(s),
+            Evidence::List(ref items) => {
+                let items: Vec<String> =
+                    items.iter().map(|item| item.to_json()).collect();
+                format!("[{}]", items.join(","))
+            },
+            Evidence::Record(ref fields) => {
+                let fields: Vec<String> =
+                    fields.iter()
+                          .map(|&(ref k, ref v)| {
+                              format!("{}:{}", json_string(k), v.to_json())
+                          })
+                          .collect();
+                format!("{{{}}}", fields.join(","))
+            },
+        }
+    }
+
+    fn is_inline(&self) -> bool {
+        match *self {
+            Evidence::Int(_) | Evidence::UInt(_) | Evidence::Str(_) => true,
+            Evidence::List(ref items) => items.iter().all(|i| i.is_inline()),
+            Evidence::Record(_) => false,
+        }
+    }
+
+    fn inline_text(&self) -> String {
+        match *self {
+            Evidence::Int(n) => n.to_string(),
+            Evidence::UInt(n) => n.to_string(),
+            Evidence::Str(ref s) => s.clone(),
+            Evidence::List(ref items) => {
+                let items: Vec<String> =
+                    items.iter().map(|item| item.inline_text()).collect();
+                format!("[{}]", items.join(", "))
+            },
+            Evidence::Record(_) => unreachable!(),
+        }
+    }
+
+    fn write_text(&self, out: &mut String, indent: usize) {
+        let pad = " ".repeat(indent);
+        match *self {
+            Evidence::Record(ref fields) => {
+                for &(ref key, ref value) in fields {
+                    if value.is_inline() {
+                        writeln!(out, "{}{}: {}", pad, key,
+                                 value.inline_text()).unwrap();
+                    } else {
+                        writeln!(out, "{}{}:", pad, key).unwrap();
+                        value.write_text(out, indent + 2);
+                    }
+                }
+            },
+            Evidence::List(ref items) => {
+                for item in items {
+                    if item.is_inline() {
+                        writeln!(out, "{}- {}", pad, item.inline_text())
+                            .unwrap();
+                    } else {
+                        writeln!(out, "{}-", pad).unwrap();
+                        item.write_text(out, indent + 2);
+                    }
+                }
+            },
+            _ => {
+                writeln!(out, "{}{}", pad, self.inline_text()).unwrap();
+            },
+        }
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                write!(out, "\\u{:04x}", c as u32).unwrap();
+            },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+macro_rules! evidence_from_int {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for Evidence {
+                fn from(n: $t) -> Evidence {
+                    Evidence::Int(n as i64)
+                }
+            }
+        )*
+    }
+}
+
+// Unsigned values only fit an Int up to i64::MAX.
+macro_rules! evidence_from_uint {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for Evidence {
+                fn from(n: $t) -> Evidence {
+                    match i64::try_from(n) {
+                        Ok(n) => Evidence::Int(n),
+                        Err(_) => Evidence::UInt(n as u128),
+                    }
+                }
+            }
+        )*
+    }
+}
+
+evidence_from_int!(i32, i64, isize);
+evidence_from_uint!(u8, u32, u64, usize, u128);
+
+impl<'a> From<&'a str> for Evidence {
+    fn from(s: &'a str) -> Evidence {
+        Evidence::Str(s.to_string())
+    }
+}
+
+impl From<String> for Evidence {
+    fn from(s: String) -> Evidence {
+        Evidence::Str(s)
+    }
+}
+
+impl From<char> for Evidence {
+    fn from(c: char) -> Evidence {
+        Evidence::Str(c.to_string())
+    }
+}
+
+// Pairs are mostly coordinates, which read best as [x, y].
+impl<A: Into<Evidence>, B: Into<Evidence>> From<(A, B)> for Evidence {
+    fn from(pair: (A, B)) -> Evidence {
+        Evidence::List(vec![pair.0.into(), pair.1.into()])
+    }
+}
+
+impl<T: Into<Evidence>> From<Vec<T>> for Evidence {
+    fn from(items: Vec<T>) -> Evidence {
+        Evidence::list(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Evidence;
+
+    fn example() -> Evidence {
+        Evidence::record()
+            .with("guard", 10)
+            .with("name", "say \"hi\"")
+            .with("cells", vec![(1, 2), (3, 4)])
+            .with("crashes", vec![Evidence::record().with("tick", 5)])
+    }
+
+    #[test]
+    fn text_output() {
+        assert_eq!("guard: 10\n\
+                    name: say \"hi\"\n\
+                    cells: [[1, 2], [3, 4]]\n\
+                    crashes:\n  \
+                      -\n    \
+                        tick: 5\n",
+                   example().to_text());
+    }
+
+    #[test]
+    fn big_numbers_stay_positive() {
+        let big = Evidence::record()
+            .with("max", u64::max_value())
+            .with("huge", u128::max_value())
+            .with("small", 7u64);
+        assert_eq!(concat!(r#"{"max":18446744073709551615,"#,
+                           r#""huge":340282366920938463463374607431768211455,"#,
+                           r#""small":7}"#),
+                   big.to_json());
+        assert_eq!(Evidence::Int(7), Evidence::from(7usize));
+    }
+
+    #[test]
+    fn json_output() {
+        assert_eq!(concat!(r#"{"guard":10,"name":"say \"hi\"","#,
+                           r#""cells":[[1,2],[3,4]],"crashes":[{"tick":5}]}"#),
+                   example().to_json());
+    }
+}
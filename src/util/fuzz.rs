@@ -0,0 +1,204 @@
+// A small mutation-based fuzzer for the input parsers.
+//
+// Each target is a function taking raw bytes. The corpus starts out as the
+// target's puzzle inputs plus anything saved under fuzz/corpus/<target>/.
+// Every corpus entry is replayed as-is first, then mutated copies are thrown
+// at the target. Returning an Error is the expected response to garbage;
+// panicking is a bug, and the offending input is written to the crash
+// directory (the corpus directory, from the command line) so it keeps getting
+// replayed after it's fixed.
+use failure::Error;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+use std::thread;
+use util::rng::Rng;
+
+pub const CORPUS_DIR: &str = "fuzz/corpus";
+
+// Panics on threads with this name are counted, not printed.
+const THREAD_NAME: &str = "fuzz";
+static QUIET_HOOK: Once = Once::new();
+
+// Mutated inputs are truncated to this length.
+const MAX_INPUT_LEN: usize = 64 * 1024;
+
+// Parsers recurse on some inputs (day 8 in particular), so give the fuzzing
+// thread a lot more stack than the default.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+// Fragments that tend to shake things loose in our line-oriented formats.
+const TOKENS: &[&str] = &[
+    "\n", "\n\n", " ", "0", "1", "-1", "59", "60", "99", "4294967296",
+    "18446744073709551616", "#", ".", "@", ",", "x", ": ", " => ", "#####",
+    ".....", "[", "]", "/", "\\", "+", "-", "|", ">", "<", "^", "v",
+];
+
+#[derive(Clone, Copy)]
+pub struct Target {
+    pub name: &'static str,
+    // Puzzle inputs used to seed the corpus (missing files are skipped).
+    pub seeds: &'static [&'static str],
+    pub run: fn(&[u8]) -> Result<(), Error>,
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub runs: usize,
+    pub errors: usize,
+    pub panics: usize,
+}
+
+// Replay the corpus, then run `runs` mutated inputs against the target.
+// Inputs that panic are saved under `crash_dir`.
+pub fn run(target: Target, runs: usize, seed: u64, crash_dir: &Path)
+        -> Result<Stats, Error> {
+    let corpus = load_corpus(&target)?;
+    let crash_dir = crash_dir.to_path_buf();
+    quiet_fuzz_panics();
+    let handle = thread::Builder::new()
+        .name(THREAD_NAME.to_string())
+        .stack_size(STACK_SIZE)
+        .spawn(move || campaign(target, corpus, runs, seed, &crash_dir))?;
+    match handle.join() {
+        Ok(stats) => stats,
+        Err(_) => Err(format_err!("Fuzzer itself panicked on {}", target.name)),
+    }
+}
+
+// The panics are reported in the summary, not as backtrace spam. The hook is
+// process-wide, so it's installed once and only silences the fuzzing threads;
+// everything else still goes to the hook that was there before.
+fn quiet_fuzz_panics() {
+    QUIET_HOOK.call_once(|| {
+        let old_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if thread::current().name() != Some(THREAD_NAME) {
+                old_hook(info);
+            }
+        }));
+    });
+}
+
+fn campaign(target: Target, corpus: Vec<Vec<u8>>, runs: usize, seed: u64,
+            crash_dir: &Path) -> Result<Stats, Error> {
+    let mut stats = Stats::default();
+    let mut rng = Rng::new(seed);
+    let mut crashes: Vec<Vec<u8>> = Vec::new();
+
+    let replays = corpus.iter().cloned();
+    let mutants = (0..runs).map(|_| mutate(&corpus, &mut rng));
+    for input in replays.chain(mutants) {
+        stats.runs += 1;
+        match panic::catch_unwind(|| (target.run)(&input)) {
+            Ok(Ok(())) => {},
+            Ok(Err(_)) => stats.errors += 1,
+            Err(_) => {
+                stats.panics += 1;
+                if !crashes.contains(&input) {
+                    crashes.push(input);
+                }
+            }
+        }
+    }
+
+    for input in crashes.iter() {
+        let path = save_crash(crash_dir, target.name, input)?;
+        println!("{}: panic reproduced by {}", target.name, path.display());
+    }
+    Ok(stats)
+}
+
+fn load_corpus(target: &Target) -> Result<Vec<Vec<u8>>, Error> {
+    let mut corpus = Vec::new();
+    for seed in target.seeds {
+        if Path::new(seed).exists() {
+            corpus.push(fs::read(seed)?);
+        }
+    }
+    let dir = Path::new(CORPUS_DIR).join(target.name);
+    if dir.is_dir() {
+        let mut paths = fs::read_dir(&dir)?
+                            .map(|e| e.map(|e| e.path()))
+                            .collect::<Result<Vec<PathBuf>, _>>()?;
+        paths.sort();
+        for path in paths {
+            corpus.push(fs::read(path)?);
+        }
+    }
+    if corpus.is_empty() {
+        // Nothing to start from, so mutate our way up from nothing.
+        corpus.push(Vec::new());
+    }
+    Ok(corpus)
+}
+
+fn save_crash(crash_dir: &Path, name: &str, input: &[u8])
+        -> Result<PathBuf, Error> {
+    let mut hasher = DefaultHasher::new();
+    input.hash(&mut hasher);
+    let dir = crash_dir.join(name);
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("crash-{:016x}", hasher.finish()));
+    fs::write(&path, input)?;
+    Ok(path)
+}
+
+// Build a new input from a random corpus entry with a few random edits.
+fn mutate(corpus: &[Vec<u8>], rng: &mut Rng) -> Vec<u8> {
+    let mut data = rng.pick(corpus).clone();
+    for _ in 0..1 + rng.below(4) {
+        let pos = rng.below(data.len() + 1);
+        match rng.below(7) {
+            // Flip a bit
+            0 if pos < data.len() => data[pos] ^= 1 << rng.below(8),
+            // Replace a byte
+            1 if pos < data.len() => data[pos] = rng.below(256) as u8,
+            // Insert a token
+            2 => {
+                let token = rng.pick(TOKENS).as_bytes();
+                data.splice(pos..pos, token.iter().cloned());
+            },
+            // Delete a range
+            3 if pos < data.len() => {
+                let end = pos + 1 + rng.below(data.len() - pos);
+                data.drain(pos..end);
+            },
+            // Duplicate a range in place
+            4 if pos < data.len() => {
+                let end = pos + 1 + rng.below((data.len() - pos).min(64));
+                let chunk = data[pos..end].to_vec();
+                data.splice(end..end, chunk);
+            },
+            // Splice in part of another corpus entry
+            5 => {
+                let other = rng.pick(corpus);
+                let start = rng.below(other.len() + 1);
+                let end = start + rng.below(other.len() - start + 1);
+                data.splice(pos..pos, other[start..end].iter().cloned());
+            },
+            // Truncate
+            _ => data.truncate(pos),
+        }
+    }
+    data.truncate(MAX_INPUT_LEN);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mutate, Rng};
+
+    #[test]
+    fn mutate_is_deterministic_for_a_seed() {
+        let corpus = vec![b"#1 @ 1,3: 4x4\n#2 @ 3,1: 4x4\n".to_vec()];
+        let mut rng1 = Rng::new(1518);
+        let mut rng2 = Rng::new(1518);
+        for _ in 0..100 {
+            assert_eq!(mutate(&corpus, &mut rng1), mutate(&corpus, &mut rng2));
+        }
+    }
+}
@@ -0,0 +1,6 @@
+pub mod config;
+pub mod evidence;
+pub mod fuzz;
+#[cfg(test)]
+pub mod prop;
+pub mod rng;
@@ -0,0 +1,107 @@
+// Bare-bones property testing for the unit tests.
+//
+// `check` generates a bunch of random inputs (small ones first, growing as it
+// goes), and asserts a property holds for each. When one fails, the input is
+// shrunk: the shrink function proposes simpler variants, and any that still
+// fail replace the original, until nothing simpler fails. The panic message
+// has the smallest failing input found plus the seed to reproduce it.
+use std::fmt::Debug;
+use util::rng::Rng;
+
+const CASES: usize = 200;
+const MAX_SIZE: usize = 50;
+const SEED: u64 = 2018;
+
+pub fn check<T, G, S, P>(name: &str, generate: G, shrink: S, property: P)
+        where T: Debug,
+              G: Fn(&mut Rng, usize) -> T,
+              S: Fn(&T) -> Vec<T>,
+              P: Fn(&T) -> bool {
+    let mut rng = Rng::new(SEED);
+    for case in 0..CASES {
+        let size = case * MAX_SIZE / CASES;
+        let input = generate(&mut rng, size);
+        if !property(&input) {
+            let minimal = minimize(input, &shrink, &property);
+            panic!("Property '{}' failed (seed {}, case {}) for: {:?}",
+                   name, SEED, case, minimal);
+        }
+    }
+}
+
+fn minimize<T, S, P>(mut input: T, shrink: &S, property: &P) -> T
+        where S: Fn(&T) -> Vec<T>,
+              P: Fn(&T) -> bool {
+    loop {
+        match shrink(&input).into_iter().find(|simpler| !property(simpler)) {
+            Some(simpler) => input = simpler,
+            None => return input,
+        }
+    }
+}
+
+// Zero, half, and one less.
+pub fn shrink_usize(n: &usize) -> Vec<usize> {
+    let mut result = Vec::new();
+    for &candidate in [0, n / 2, n.saturating_sub(1)].iter() {
+        if candidate < *n && !result.contains(&candidate) {
+            result.push(candidate);
+        }
+    }
+    result
+}
+
+// Drop the back half, the front half, then each single element.
+pub fn shrink_vec<T: Clone>(v: &Vec<T>) -> Vec<Vec<T>> {
+    let mut result = Vec::new();
+    if v.len() > 1 {
+        result.push(v[..v.len() / 2].to_vec());
+        result.push(v[v.len() / 2..].to_vec());
+    }
+    for i in 0..v.len() {
+        let mut smaller = v.clone();
+        smaller.remove(i);
+        result.push(smaller);
+    }
+    result
+}
+
+pub fn shrink_string(s: &String) -> Vec<String> {
+    shrink_vec(&s.chars().collect())
+        .into_iter()
+        .map(|chars| chars.into_iter().collect())
+        .collect()
+}
+
+// A string of up to `size` characters drawn from `alphabet`.
+pub fn string_from(rng: &mut Rng, size: usize, alphabet: &str) -> String {
+    let alphabet: Vec<char> = alphabet.chars().collect();
+    let len = rng.below(size + 1);
+    (0..len).map(|_| *rng.pick(&alphabet)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shrinking_finds_a_minimal_counterexample() {
+        // "Strings never contain two b's" should shrink down to "bb".
+        let result = ::std::panic::catch_unwind(|| {
+            check("fewer than two b's",
+                  |rng, size| string_from(rng, size, "ab"),
+                  shrink_string,
+                  |s| s.matches('b').count() < 2);
+        });
+        let message = result.unwrap_err();
+        let message = message.downcast_ref::<String>().unwrap();
+        assert!(message.ends_with("for: \"bb\""), "{}", message);
+    }
+
+    #[test]
+    fn shrink_usize_only_proposes_smaller_values() {
+        assert_eq!(Vec::<usize>::new(), shrink_usize(&0));
+        assert_eq!(vec![0], shrink_usize(&1));
+        assert_eq!(vec![0, 5, 9], shrink_usize(&10));
+    }
+}